@@ -0,0 +1,84 @@
+//! Measures `IsFollowing` throughput against `SocialNetworkServer` as the number of worker
+//! threads grows. Reads share the network lock, so throughput should scale with cores.
+//!
+//! Run with `cargo bench --bench read_throughput`.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use socialnetwork::server::social_network::social_network_service_server::SocialNetworkService;
+use socialnetwork::server::social_network::{
+    BatchMutateRequest, IsFollowingRequest, Mutation, MutationKind,
+};
+use socialnetwork::server::SocialNetworkServer;
+use tonic::Request;
+
+const USERS: u64 = 10_000;
+const EDGES: usize = 200_000;
+const VERSIONS: u64 = 20;
+const READS_PER_TASK: usize = 200_000;
+
+async fn populate(server: &SocialNetworkServer) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let edges_per_version = EDGES / usize::try_from(VERSIONS).unwrap();
+    for _ in 0..VERSIONS {
+        let mutations = (0..edges_per_version)
+            .map(|_| Mutation {
+                kind: if rng.gen_bool(0.8) { MutationKind::Follow } else { MutationKind::Unfollow }.into(),
+                follower_id: rng.gen_range(0..USERS),
+                followee_id: rng.gen_range(0..USERS),
+            })
+            .collect();
+        let request = Request::new(BatchMutateRequest { mutations, commit: true });
+        server.batch_mutate(request).await.unwrap();
+    }
+}
+
+async fn read_task(server: Arc<SocialNetworkServer>, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..READS_PER_TASK {
+        let request = Request::new(IsFollowingRequest {
+            follower_id: rng.gen_range(0..USERS),
+            followee_id: rng.gen_range(0..USERS),
+            version: Some(rng.gen_range(0..=VERSIONS)),
+        });
+        server.is_following(request).await.unwrap();
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn main() {
+    let server = Arc::new(SocialNetworkServer::new());
+    tokio::runtime::Runtime::new().unwrap().block_on(populate(&server));
+
+    let max_threads = thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get);
+    let thread_counts = std::iter::successors(Some(1), |&n| Some(n * 2))
+        .take_while(|&n| n < max_threads)
+        .chain(std::iter::once(max_threads));
+
+    println!("{:>8} {:>16} {:>10}", "threads", "reads/sec", "speedup");
+    let mut baseline = None;
+    for threads in thread_counts {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(threads)
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        runtime.block_on(async {
+            let tasks: Vec<_> = (0..threads as u64)
+                .map(|seed| tokio::spawn(read_task(Arc::clone(&server), seed)))
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+        let reads_per_sec = (threads * READS_PER_TASK) as f64 / start.elapsed().as_secs_f64();
+
+        let baseline = *baseline.get_or_insert(reads_per_sec);
+        println!("{threads:>8} {reads_per_sec:>16.0} {:>9.2}x", reads_per_sec / baseline);
+    }
+}
@@ -1,4 +1,11 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/social_network.proto")?;
+    // The encoded descriptor set is served by gRPC reflection
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("social_network_descriptor.bin"))
+        .compile(&["proto/social_network.proto"], &["proto"])?;
     Ok(())
-} 
\ No newline at end of file
+}
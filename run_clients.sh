@@ -33,9 +33,10 @@ cleanup() {
         fi
     done
     
-    # Kill server process
+    # Stop the server with SIGTERM and wait for it to drain requests and flush its data
     if kill -0 $SERVER_PID 2>/dev/null; then
-        kill $SERVER_PID
+        kill -TERM $SERVER_PID
+        wait $SERVER_PID
     fi
     
     echo "All processes stopped."
@@ -0,0 +1,321 @@
+//! Graph analytics (triangles, clustering, degree statistics) over the follow graph at a given version.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::SocialNetwork;
+
+/// Map of `user_id` to a set of neighbouring user ids.
+type Adjacency = HashMap<u64, HashSet<u64>>;
+
+/// Summary statistics of the follow graph at a version
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphStats {
+    /// Version the statistics were computed at
+    pub version: u64,
+
+    /// Number of users with at least one follower or followee
+    pub user_count: u64,
+
+    /// Number of follow edges
+    pub edge_count: u64,
+
+    /// Map of in-degree (follower count) to the number of users with that degree
+    pub in_degree_histogram: BTreeMap<u64, u64>,
+
+    /// Map of out-degree (followee count) to the number of users with that degree
+    pub out_degree_histogram: BTreeMap<u64, u64>,
+
+    /// Largest in-degree and the users that have it, sorted by user id
+    pub max_in_degree: u64,
+    pub max_in_degree_users: Vec<u64>,
+
+    /// Largest out-degree and the users that have it, sorted by user id
+    pub max_out_degree: u64,
+    pub max_out_degree_users: Vec<u64>,
+
+    /// Fraction of follow edges whose reverse edge also exists (0 for an empty graph)
+    pub reciprocity: f64,
+}
+
+impl SocialNetwork {
+    /// Followee and follower adjacency at a version (use current state if not specified).
+    ///
+    /// The current state borrows `follows` / `is_followed` directly; historical versions
+    /// are rebuilt from every follow interval, so this is only for graph-wide metrics.
+    pub(crate) fn adjacency_at(&self, version: Option<u64>) -> (Cow<'_, Adjacency>, Cow<'_, Adjacency>) {
+        let Some(version) = version else {
+            return (Cow::Borrowed(&self.follows), Cow::Borrowed(&self.is_followed));
+        };
+
+        let mut follows = Adjacency::new();
+        let mut is_followed = Adjacency::new();
+        if version <= self.version {
+            for (&(follower_id, followee_id), intervals) in &self.follow_intervals {
+                if intervals.iter().any(|interval| interval.is_active(version)) {
+                    follows.entry(follower_id).or_default().insert(followee_id);
+                    is_followed.entry(followee_id).or_default().insert(follower_id);
+                }
+            }
+        }
+        (Cow::Owned(follows), Cow::Owned(is_followed))
+    }
+
+    /// Local clustering coefficient of a user in the directed follow graph.
+    ///
+    /// The neighbourhood of a user is everyone they follow or are followed by; the
+    /// coefficient is the fraction of the `k * (k - 1)` possible directed edges between
+    /// those `k` neighbours that exist. Users with fewer than two neighbours score 0.
+    ///
+    /// Historical coefficients only look at the follow history of the user and their
+    /// neighbours, not the whole graph.
+    #[must_use]
+    pub fn clustering_coefficient(&self, user_id: u64, version: Option<u64>) -> f64 {
+        let Some(version) = version else {
+            return local_clustering(&self.follows, &self.is_followed, user_id);
+        };
+
+        let neighbours: HashSet<u64> = self
+            .followees_at(user_id, Some(version))
+            .into_iter()
+            .chain(self.followers_at(user_id, Some(version)))
+            .collect();
+        let links = neighbours
+            .iter()
+            .map(|&neighbour| {
+                self.followee_history.get(&neighbour).map_or(0, |followees| {
+                    followees
+                        .iter()
+                        .filter(|&&followee_id| {
+                            neighbours.contains(&followee_id) && self.is_following(neighbour, followee_id, Some(version))
+                        })
+                        .count()
+                })
+            })
+            .sum();
+        clustering(neighbours.len(), links)
+    }
+
+    /// Local clustering coefficient of every user present in the graph at a version
+    #[must_use]
+    pub fn clustering_coefficients(&self, version: Option<u64>) -> HashMap<u64, f64> {
+        let (follows, is_followed) = self.adjacency_at(version);
+        follows
+            .iter()
+            .chain(is_followed.iter())
+            .filter(|(_, neighbours)| !neighbours.is_empty())
+            .map(|(&user_id, _)| (user_id, local_clustering(&follows, &is_followed, user_id)))
+            .collect()
+    }
+
+    /// Number of triangles in the follow graph at a version.
+    ///
+    /// Edge direction is ignored: three users form a triangle when every pair of them is
+    /// connected by a follow in at least one direction. Each triangle is counted once.
+    #[must_use]
+    pub fn triangle_count(&self, version: Option<u64>) -> u64 {
+        let (follows, is_followed) = self.adjacency_at(version);
+
+        let mut undirected = Adjacency::new();
+        for (&user_id, neighbours) in follows.iter().chain(is_followed.iter()) {
+            undirected.entry(user_id).or_default().extend(neighbours);
+        }
+
+        let mut triangles = 0;
+        for (&a, a_neighbours) in &undirected {
+            for &b in a_neighbours.iter().filter(|&&b| b > a) {
+                triangles += undirected[&b]
+                    .iter()
+                    .filter(|&&c| c > b && a_neighbours.contains(&c))
+                    .count() as u64;
+            }
+        }
+        triangles
+    }
+
+    /// Degree distribution and summary statistics at a version (use current version if not specified)
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn stats(&self, version: Option<u64>) -> GraphStats {
+        let (follows, is_followed) = self.adjacency_at(version);
+        let mut stats = GraphStats {
+            version: version.unwrap_or(self.version),
+            ..GraphStats::default()
+        };
+
+        let users: HashSet<u64> = follows
+            .iter()
+            .chain(is_followed.iter())
+            .filter(|(_, neighbours)| !neighbours.is_empty())
+            .map(|(&user_id, _)| user_id)
+            .collect();
+
+        let mut mutual_edges = 0;
+        for &user_id in &users {
+            let followees = follows.get(&user_id);
+            let out_degree = followees.map_or(0, HashSet::len) as u64;
+            let in_degree = is_followed.get(&user_id).map_or(0, HashSet::len) as u64;
+
+            stats.edge_count += out_degree;
+            *stats.out_degree_histogram.entry(out_degree).or_default() += 1;
+            *stats.in_degree_histogram.entry(in_degree).or_default() += 1;
+            track_max(&mut stats.max_out_degree, &mut stats.max_out_degree_users, out_degree, user_id);
+            track_max(&mut stats.max_in_degree, &mut stats.max_in_degree_users, in_degree, user_id);
+
+            if let Some(followees) = followees {
+                mutual_edges += followees
+                    .iter()
+                    .filter(|followee| follows.get(followee).is_some_and(|f| f.contains(&user_id)))
+                    .count() as u64;
+            }
+        }
+
+        stats.user_count = users.len() as u64;
+        stats.max_in_degree_users.sort_unstable();
+        stats.max_out_degree_users.sort_unstable();
+        if stats.edge_count > 0 {
+            stats.reciprocity = mutual_edges as f64 / stats.edge_count as f64;
+        }
+        stats
+    }
+}
+
+fn track_max(max_degree: &mut u64, max_users: &mut Vec<u64>, degree: u64, user_id: u64) {
+    if degree > *max_degree {
+        *max_degree = degree;
+        max_users.clear();
+    }
+    if degree == *max_degree && degree > 0 {
+        max_users.push(user_id);
+    }
+}
+
+fn local_clustering(follows: &Adjacency, is_followed: &Adjacency, user_id: u64) -> f64 {
+    let mut neighbours: HashSet<u64> = HashSet::new();
+    if let Some(followees) = follows.get(&user_id) {
+        neighbours.extend(followees);
+    }
+    if let Some(followers) = is_followed.get(&user_id) {
+        neighbours.extend(followers);
+    }
+
+    let links = neighbours
+        .iter()
+        .filter_map(|neighbour| follows.get(neighbour))
+        .map(|followees| followees.iter().filter(|f| neighbours.contains(f)).count())
+        .sum();
+    clustering(neighbours.len(), links)
+}
+
+/// Fraction of the `k * (k - 1)` possible directed links between `k` neighbours that exist
+#[allow(clippy::cast_precision_loss)]
+fn clustering(k: usize, links: usize) -> f64 {
+    if k < 2 {
+        return 0.0;
+    }
+    links as f64 / (k * (k - 1)) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::SocialNetwork;
+
+    #[test]
+    fn test_triangle_count() {
+        let mut network = SocialNetwork::new();
+
+        // 1 -> 2 -> 3 -> 1 is a triangle, so is 1 <-> 2, 2 -> 4, 4 -> 1
+        network.follow(1, 2).unwrap();
+        network.follow(2, 3).unwrap();
+        network.follow(3, 1).unwrap();
+        network.follow(2, 1).unwrap();
+        network.follow(2, 4).unwrap();
+        network.follow(4, 1).unwrap();
+        assert_eq!(network.triangle_count(None), 2);
+
+        // An open wedge is not a triangle
+        network.follow(5, 6).unwrap();
+        network.follow(6, 7).unwrap();
+        assert_eq!(network.triangle_count(None), 2);
+    }
+
+    #[test]
+    fn test_clustering_coefficient() {
+        let mut network = SocialNetwork::new();
+
+        // User 1 follows 2 and 3, and 2 follows 3: one of two possible directed links
+        network.follow(1, 2).unwrap();
+        network.follow(1, 3).unwrap();
+        network.follow(2, 3).unwrap();
+        assert!((network.clustering_coefficient(1, None) - 0.5).abs() < f64::EPSILON);
+
+        network.follow(3, 2).unwrap();
+        assert!((network.clustering_coefficient(1, None) - 1.0).abs() < f64::EPSILON);
+
+        // Fewer than two neighbours
+        assert!(network.clustering_coefficient(999, None).abs() < f64::EPSILON);
+
+        let coefficients = network.clustering_coefficients(None);
+        assert_eq!(coefficients.len(), 3);
+        assert!((coefficients[&1] - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_analytics_at_version() {
+        let mut network = SocialNetwork::new();
+
+        network.follow(1, 2).unwrap();
+        network.follow(2, 3).unwrap();
+        network.follow(3, 1).unwrap();
+        let v1 = network.commit();
+
+        network.unfollow(3, 1).unwrap();
+        let v2 = network.commit();
+
+        assert_eq!(network.triangle_count(Some(v1)), 1);
+        assert_eq!(network.triangle_count(Some(v2)), 0);
+        assert_eq!(network.triangle_count(None), 0);
+        assert!((network.clustering_coefficient(1, Some(v1)) - 0.5).abs() < f64::EPSILON);
+        assert!(network.clustering_coefficient(1, Some(v2)).abs() < f64::EPSILON);
+
+        // The per-user historical coefficient agrees with the whole-graph rebuild
+        let coefficients = network.clustering_coefficients(Some(v1));
+        for user_id in 1..=3 {
+            assert!((coefficients[&user_id] - network.clustering_coefficient(user_id, Some(v1))).abs() < f64::EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut network = SocialNetwork::new();
+        assert_eq!(network.stats(None).edge_count, 0);
+
+        // 1 <-> 2, 1 -> 3, 4 -> 3
+        network.follow(1, 2).unwrap();
+        network.follow(2, 1).unwrap();
+        network.follow(1, 3).unwrap();
+        network.follow(4, 3).unwrap();
+        let v1 = network.commit();
+
+        let stats = network.stats(None);
+        assert_eq!(stats.version, v1);
+        assert_eq!(stats.user_count, 4);
+        assert_eq!(stats.edge_count, 4);
+        assert_eq!(stats.out_degree_histogram, BTreeMap::from([(0, 1), (1, 2), (2, 1)]));
+        assert_eq!(stats.in_degree_histogram, BTreeMap::from([(0, 1), (1, 2), (2, 1)]));
+        assert_eq!(stats.max_out_degree, 2);
+        assert_eq!(stats.max_out_degree_users, vec![1]);
+        assert_eq!(stats.max_in_degree, 2);
+        assert_eq!(stats.max_in_degree_users, vec![3]);
+        assert!((stats.reciprocity - 0.5).abs() < f64::EPSILON);
+
+        // Historical stats are unaffected by later changes
+        network.unfollow(4, 3).unwrap();
+        network.commit();
+        assert_eq!(network.stats(Some(v1)).edge_count, 4);
+        assert_eq!(network.stats(None).edge_count, 3);
+        assert_eq!(network.stats(None).user_count, 3);
+    }
+}
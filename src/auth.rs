@@ -0,0 +1,364 @@
+//! Bearer token authentication and role-based authorization.
+//!
+//! Callers present a static token from a token file or an HMAC-signed JWT, the identity it maps
+//! to is attached to the request, and each RPC requires a minimum role.
+
+// Interceptors must fail with `tonic::Status`
+#![allow(clippy::result_large_err)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Status};
+
+/// Metadata entry carrying `Bearer <token>`
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+
+/// Roles granted to callers, each including the permissions of the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Queries and streams
+    #[default]
+    Reader,
+    /// Follow and unfollow as the caller's own user
+    Writer,
+    /// Commit, and follow and unfollow on behalf of any user
+    Admin,
+}
+
+/// Role each RPC requires unless the config overrides it
+const DEFAULT_ROLES: [(&str, Role); 27] = [
+    ("Follow", Role::Writer),
+    ("Unfollow", Role::Writer),
+    // Bulk import, and it can commit
+    ("BatchMutate", Role::Admin),
+    ("Block", Role::Writer),
+    ("Unblock", Role::Writer),
+    ("Mute", Role::Writer),
+    ("Unmute", Role::Writer),
+    ("Commit", Role::Admin),
+    ("IsFollowing", Role::Reader),
+    ("BatchIsFollowing", Role::Reader),
+    ("FollowsAny", Role::Reader),
+    ("FollowedByMany", Role::Reader),
+    ("GetFollowers", Role::Reader),
+    ("GetFollowees", Role::Reader),
+    ("GetFollowerCount", Role::Reader),
+    ("GetFolloweeCount", Role::Reader),
+    ("StreamFollowers", Role::Reader),
+    ("StreamFollowees", Role::Reader),
+    ("GetCurrentVersion", Role::Reader),
+    ("GetGraphStats", Role::Reader),
+    ("WatchChanges", Role::Reader),
+    ("SubscribeUserEvents", Role::Reader),
+    ("IsBlocked", Role::Reader),
+    ("GetBlockedUsers", Role::Reader),
+    ("IsMuted", Role::Reader),
+    ("GetMutedUsers", Role::Reader),
+    ("GetFeedFollowees", Role::Reader),
+];
+
+/// Role required to call each RPC, by method name. Methods without an entry require admin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RolePolicy {
+    roles: HashMap<String, Role>,
+}
+
+impl RolePolicy {
+    /// The default policy with `overrides` applied
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an override names a method the service does not have.
+    pub fn new(overrides: &BTreeMap<String, Role>) -> Result<Self, String> {
+        let mut policy = Self::default();
+        for (method, &role) in overrides {
+            let required = policy.roles.get_mut(method).ok_or_else(|| format!("Unknown RPC method {method} in roles"))?;
+            *required = role;
+        }
+        Ok(policy)
+    }
+
+    /// Role needed to call `method`
+    #[must_use]
+    pub fn required_role(&self, method: &str) -> Role {
+        self.roles.get(method).copied().unwrap_or(Role::Admin)
+    }
+}
+
+impl Default for RolePolicy {
+    fn default() -> Self {
+        Self { roles: DEFAULT_ROLES.iter().map(|&(method, role)| (method.to_string(), role)).collect() }
+    }
+}
+
+/// Identity of an authenticated caller, available to handlers as a request extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    /// Name of the caller, for logs and error messages
+    pub name: String,
+
+    /// User the caller acts as; unless it is an admin, it may only follow and unfollow as this user
+    pub user_id: Option<u64>,
+
+    pub role: Role,
+}
+
+impl Caller {
+    /// Whether the caller may change the followees of `follower_id`
+    #[must_use]
+    pub fn can_act_as(&self, follower_id: u64) -> bool {
+        self.role == Role::Admin || self.user_id == Some(follower_id)
+    }
+}
+
+/// Where bearer tokens are checked against. At least one source must be set.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// TOML file listing static tokens and the callers they identify
+    pub token_file: Option<PathBuf>,
+
+    /// File holding the secret JWTs are signed with (HS256, HS384 or HS512)
+    pub jwt_secret_file: Option<PathBuf>,
+
+    /// `iss` claim JWTs must carry, if any
+    pub jwt_issuer: Option<String>,
+
+    /// Role required by RPC method name, overriding the defaults: reader for queries and
+    /// streams, writer for single follows, unfollows, blocks and mutes, admin for `Commit` and
+    /// `BatchMutate`
+    #[serde(default)]
+    pub roles: BTreeMap<String, Role>,
+}
+
+/// Entry of a token file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StaticToken {
+    token: String,
+    name: String,
+    user_id: Option<u64>,
+    #[serde(default)]
+    role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenFile {
+    #[serde(default)]
+    tokens: Vec<StaticToken>,
+}
+
+/// Claims read from a JWT; `exp` is required and checked by the validation
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    user_id: Option<u64>,
+    #[serde(default)]
+    role: Role,
+}
+
+/// Validates bearer tokens and maps them to callers
+#[derive(Clone)]
+pub struct Authenticator {
+    tokens: HashMap<String, Caller>,
+    jwt: Option<(DecodingKey, Validation)>,
+}
+
+impl std::fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the tokens or the secret
+        f.debug_struct("Authenticator")
+            .field("tokens", &self.tokens.len())
+            .field("jwt", &self.jwt.is_some())
+            .finish()
+    }
+}
+
+impl Authenticator {
+    /// An authenticator accepting nothing until tokens or a JWT secret are added
+    #[must_use]
+    pub fn new() -> Self {
+        Self { tokens: HashMap::new(), jwt: None }
+    }
+
+    /// Read the token file and JWT secret named by the config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if neither source is set, or if a file cannot be read or parsed.
+    pub fn load(config: &AuthConfig) -> Result<Self, String> {
+        if config.token_file.is_none() && config.jwt_secret_file.is_none() {
+            return Err("Authentication needs a token_file or a jwt_secret_file".to_string());
+        }
+
+        let mut authenticator = Self::new();
+        if let Some(path) = &config.token_file {
+            let file: TokenFile = toml::from_str(&read_file(path)?)
+                .map_err(|e| format!("Invalid token file {}: {e}", path.display()))?;
+            for entry in file.tokens {
+                let caller = Caller { name: entry.name, user_id: entry.user_id, role: entry.role };
+                authenticator = authenticator.with_token(entry.token, caller);
+            }
+        }
+        if let Some(path) = &config.jwt_secret_file {
+            let secret = read_file(path)?;
+            authenticator = authenticator.with_jwt_secret(secret.trim().as_bytes(), config.jwt_issuer.as_deref());
+        }
+        Ok(authenticator)
+    }
+
+    /// Accept a static token as `caller`
+    #[must_use]
+    pub fn with_token(mut self, token: impl Into<String>, caller: Caller) -> Self {
+        self.tokens.insert(token.into(), caller);
+        self
+    }
+
+    /// Accept JWTs signed with `secret`, and issued by `issuer` if given
+    #[must_use]
+    pub fn with_jwt_secret(mut self, secret: &[u8], issuer: Option<&str>) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.algorithms = vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+        if let Some(issuer) = issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        self.jwt = Some((DecodingKey::from_secret(secret), validation));
+        self
+    }
+
+    /// Identify the caller from the bearer token in `metadata`
+    ///
+    /// # Errors
+    ///
+    /// Returns `UNAUTHENTICATED` if the token is missing, unknown, badly signed or expired.
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Result<Caller, Status> {
+        let token = metadata
+            .get(AUTHORIZATION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Missing bearer token"))?;
+
+        if let Some(caller) = self.tokens.get(token) {
+            return Ok(caller.clone());
+        }
+        let (key, validation) = self.jwt.as_ref().ok_or_else(|| Status::unauthenticated("Unknown token"))?;
+        let claims = jsonwebtoken::decode::<Claims>(token, key, validation)
+            .map_err(|e| Status::unauthenticated(format!("Invalid token: {e}")))?
+            .claims;
+        Ok(Caller { name: claims.sub, user_id: claims.user_id, role: claims.role })
+    }
+
+    /// Interceptor body: authenticate the request and attach its [`Caller`]
+    ///
+    /// # Errors
+    ///
+    /// Returns `UNAUTHENTICATED` if the request has no valid bearer token.
+    pub fn intercept(&self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let caller = self.authenticate(request.metadata())?;
+        request.extensions_mut().insert(caller);
+        Ok(request)
+    }
+}
+
+impl Default for Authenticator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct TestClaims<'a> {
+        sub: &'a str,
+        user_id: u64,
+        role: &'a str,
+        iss: &'a str,
+        exp: u64,
+    }
+
+    fn jwt(secret: &[u8], iss: &str, exp: u64) -> String {
+        let claims = TestClaims { sub: "alice", user_id: 1, role: "writer", iss, exp };
+        jsonwebtoken::encode(&Header::default(), &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    fn request(token: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(token) = token {
+            request.metadata_mut().insert(AUTHORIZATION_HEADER, format!("Bearer {token}").parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let dir = std::env::temp_dir().join(format!("socialnetwork-auth-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let token_file = dir.join("tokens.toml");
+        std::fs::write(&token_file, "[[tokens]]\ntoken = \"ops-token\"\nname = \"ops\"\nrole = \"admin\"\n").unwrap();
+        let secret_file = dir.join("jwt.secret");
+        std::fs::write(&secret_file, "secret\n").unwrap();
+        let config = AuthConfig {
+            token_file: Some(token_file),
+            jwt_secret_file: Some(secret_file),
+            jwt_issuer: Some("socialnetwork".to_string()),
+            ..AuthConfig::default()
+        };
+        let authenticator = Authenticator::load(&config).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let ops = authenticator.intercept(request(Some("ops-token"))).unwrap();
+        let ops = ops.extensions().get::<Caller>().unwrap();
+        assert!(ops.role == Role::Admin && ops.can_act_as(7));
+
+        let exp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 60;
+        let alice = authenticator.authenticate(request(Some(&jwt(b"secret", "socialnetwork", exp))).metadata()).unwrap();
+        assert_eq!(alice, Caller { name: "alice".to_string(), user_id: Some(1), role: Role::Writer });
+        assert!(alice.can_act_as(1) && !alice.can_act_as(2));
+
+        for rejected in [
+            request(None),
+            request(Some("guessed")),
+            request(Some(&jwt(b"other secret", "socialnetwork", exp))),
+            request(Some(&jwt(b"secret", "someone else", exp))),
+            request(Some(&jwt(b"secret", "socialnetwork", exp - 3600))),
+        ] {
+            let status = authenticator.intercept(rejected).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        }
+
+        assert!(Authenticator::load(&AuthConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_role_policy() {
+        let policy = RolePolicy::default();
+        assert_eq!(policy.required_role("GetFollowers"), Role::Reader);
+        assert_eq!(policy.required_role("Follow"), Role::Writer);
+        assert_eq!(policy.required_role("Commit"), Role::Admin);
+        assert_eq!(policy.required_role("BatchMutate"), Role::Admin);
+        assert_eq!(policy.required_role("Compact"), Role::Admin);
+        assert!(Role::Reader < Role::Writer && Role::Writer < Role::Admin);
+
+        let policy = RolePolicy::new(&BTreeMap::from([("Commit".to_string(), Role::Writer)])).unwrap();
+        assert_eq!(policy.required_role("Commit"), Role::Writer);
+        assert!(RolePolicy::new(&BTreeMap::from([("Comit".to_string(), Role::Writer)])).is_err());
+    }
+}
@@ -1,9 +1,15 @@
 use socialnetwork::server::social_network::social_network_service_client::SocialNetworkServiceClient;
 use socialnetwork::server::social_network::*;
+use socialnetwork::tls::ClientTls;
+use std::path::PathBuf;
 use std::time::Duration;
 use tokio::time::sleep;
 use rand::Rng;
 use clap::Parser;
+use socialnetwork::auth::AUTHORIZATION_HEADER;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::Channel;
+use tonic::Request;
 
 #[derive(Debug)]
 enum Action {
@@ -51,13 +57,51 @@ struct Args {
     /// Verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    /// PEM CA certificate to verify the server with; enables TLS (use an https:// server address)
+    #[arg(long)]
+    tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate, for servers requiring mutual TLS
+    #[arg(long, requires_all = ["tls_key", "tls_ca"])]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the client certificate
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Name to verify the server certificate against, if not the host of the server address
+    #[arg(long, requires = "tls_ca")]
+    tls_domain: Option<String>,
+
+    /// Bearer token (static token or JWT) sent with every request
+    #[arg(long)]
+    token: Option<String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
     
-    let mut client = SocialNetworkServiceClient::connect(args.server.clone()).await?;
+    let mut endpoint = Channel::from_shared(args.server.clone())?;
+    if let Some(ca_cert_path) = args.tls_ca.clone() {
+        let tls = ClientTls {
+            ca_cert_path,
+            identity: args.tls_cert.clone().zip(args.tls_key.clone()),
+            domain_name: args.tls_domain.clone(),
+        };
+        endpoint = endpoint.tls_config(tls.load()?)?;
+    }
+    let authorization: Option<MetadataValue<Ascii>> =
+        args.token.as_ref().map(|token| format!("Bearer {token}").parse()).transpose()?;
+    // Interceptors must fail with `tonic::Status`
+    #[allow(clippy::result_large_err)]
+    let mut client = SocialNetworkServiceClient::with_interceptor(endpoint.connect().await?, move |mut request: Request<()>| {
+        if let Some(authorization) = &authorization {
+            request.metadata_mut().insert(AUTHORIZATION_HEADER, authorization.clone());
+        }
+        Ok(request)
+    });
     
     if args.verbose {
         println!("Client connected to Social Network gRPC Server at {}", args.server);
@@ -82,6 +126,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let request = tonic::Request::new(FollowRequest {
                     follower_id: follower_id,
                     followee_id: followee_id,
+                    expected_version: None,
+                    idempotency_key: None,
                 });
                     
                 match client.follow(request).await {
@@ -99,13 +145,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
                     
                 // Commit after follow
-                let _ = client.commit(tonic::Request::new(CommitRequest {})).await;
+                let _ = client.commit(tonic::Request::new(CommitRequest { expected_version: None, idempotency_key: None })).await;
             }
             Action::Unfollow => {
                 // Unfollow a random user
                 let request = tonic::Request::new(UnfollowRequest {
                     follower_id: follower_id,
                     followee_id: followee_id,
+                    expected_version: None,
+                    idempotency_key: None,
                 });
                     
                 match client.unfollow(request).await {
@@ -123,7 +171,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
                     
                 // Commit after unfollow
-                let _ = client.commit(tonic::Request::new(CommitRequest {})).await;
+                let _ = client.commit(tonic::Request::new(CommitRequest { expected_version: None, idempotency_key: None })).await;
             }
             Action::Check => {
                 // Check if following a random user
@@ -1,17 +1,315 @@
-use socialnetwork::server::create_server;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use socialnetwork::auth::{AuthConfig, Authenticator};
+use socialnetwork::config::{AutocommitPolicy, LogFormat, LogLevel, PersistenceMode, ServerConfig};
+use socialnetwork::metrics::{metrics_server, MetricsLayer};
+use socialnetwork::request_id::RequestIdLayer;
+use socialnetwork::server::social_network::social_network_service_server::SocialNetworkServiceServer;
+use socialnetwork::server::social_network::FILE_DESCRIPTOR_SET;
+use socialnetwork::server::SocialNetworkServer;
+use socialnetwork::tls::ServerTls;
+use tokio::sync::Notify;
+use tonic::server::NamedService;
+use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Server;
+use tonic::Status;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+#[derive(Parser)]
+#[command(name = "social-network-server")]
+#[command(about = "gRPC server for the versioned social network")]
+struct Args {
+    /// TOML config file; flags override the values it sets
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Address to listen on
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+
+    /// Port to listen on, keeping the configured host
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Address of the Prometheus metrics endpoint
+    #[arg(long)]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Directory holding persisted data
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+
+    /// Where to keep the follow graph
+    #[arg(long, value_enum)]
+    persistence: Option<PersistenceMode>,
+
+    /// When to commit without an explicit Commit request
+    #[arg(long, value_enum)]
+    autocommit: Option<AutocommitPolicy>,
+
+    /// Time between commits with the interval autocommit policy (milliseconds)
+    #[arg(long)]
+    autocommit_interval_ms: Option<u64>,
+
+    /// How long a shutdown waits for in-flight requests (milliseconds)
+    #[arg(long)]
+    drain_timeout_ms: Option<u64>,
+
+    /// Logging verbosity
+    #[arg(long, value_enum)]
+    log_level: Option<LogLevel>,
+
+    /// Log output format
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
+
+    /// PEM certificate chain to serve TLS with
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the TLS certificate
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM CA certificate that client certificates must be signed by (mutual TLS)
+    #[arg(long)]
+    tls_client_ca: Option<PathBuf>,
+
+    /// TOML file of static bearer tokens; enables authentication
+    #[arg(long)]
+    auth_token_file: Option<PathBuf>,
+
+    /// File holding the HMAC secret of bearer JWTs; enables authentication
+    #[arg(long)]
+    auth_jwt_secret_file: Option<PathBuf>,
+}
+
+impl Args {
+    /// Load the config file (or defaults) and apply the flags on top
+    fn into_config(self) -> Result<ServerConfig, String> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::load(path)?,
+            None => ServerConfig::default(),
+        };
+
+        if let Some(listen) = self.listen {
+            config.listen = listen;
+        }
+        if let Some(port) = self.port {
+            config.listen.set_port(port);
+        }
+        if let Some(metrics_listen) = self.metrics_listen {
+            config.metrics_listen = metrics_listen;
+        }
+        if let Some(data_dir) = self.data_dir {
+            config.data_dir = data_dir;
+        }
+        if let Some(persistence) = self.persistence {
+            config.persistence = persistence;
+        }
+        if let Some(autocommit) = self.autocommit {
+            config.autocommit = autocommit;
+        }
+        if let Some(interval_ms) = self.autocommit_interval_ms {
+            config.autocommit_interval_ms = interval_ms;
+        }
+        if let Some(drain_timeout_ms) = self.drain_timeout_ms {
+            config.drain_timeout_ms = drain_timeout_ms;
+        }
+        if let Some(log_level) = self.log_level {
+            config.log_level = log_level;
+        }
+        if let Some(log_format) = self.log_format {
+            config.log_format = log_format;
+        }
+        if let (Some(cert_path), Some(key_path)) = (self.tls_cert, self.tls_key) {
+            let client_ca_path = config.tls.take().and_then(|tls| tls.client_ca_path);
+            config.tls = Some(ServerTls { cert_path, key_path, client_ca_path });
+        }
+        if let Some(client_ca_path) = self.tls_client_ca {
+            let tls = config.tls.as_mut().ok_or("--tls-client-ca requires a server certificate and key")?;
+            tls.client_ca_path = Some(client_ca_path);
+        }
+        if self.auth_token_file.is_some() || self.auth_jwt_secret_file.is_some() {
+            let auth = config.auth.get_or_insert_with(AuthConfig::default);
+            auth.token_file = self.auth_token_file.or_else(|| auth.token_file.take());
+            auth.jwt_secret_file = self.auth_jwt_secret_file.or_else(|| auth.jwt_secret_file.take());
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::1]:50051".parse()?;
-    let server = create_server();
+    let config = Args::parse().into_config()?;
+    init_tracing(&config);
+    let server = Arc::new(SocialNetworkServer::with_config(&config)?);
+
+    // Health checks report NOT_SERVING, and social network RPCs are rejected, until the
+    // persisted state has been replayed
+    let (mut health, health_service) = tonic_health::server::health_reporter();
+    set_health(&mut health, ServingStatus::NotServing).await;
+    let ready = Arc::new(AtomicBool::new(false));
+    let authenticator = config.auth.as_ref().map(Authenticator::load).transpose()?.map(Arc::new);
+    // Interceptors must fail with `tonic::Status`
+    #[allow(clippy::result_large_err)]
+    let social_network_service = {
+        let ready = Arc::clone(&ready);
+        InterceptedService::new(SocialNetworkServiceServer::from_arc(Arc::clone(&server)), move |request| {
+            if !ready.load(Ordering::Acquire) {
+                return Err(Status::unavailable("Server is replaying its change log"));
+            }
+            match &authenticator {
+                Some(authenticator) => authenticator.intercept(request),
+                None => Ok(request),
+            }
+        })
+    };
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()?;
+
+    let metrics = tokio::spawn(metrics_server(config.metrics_listen, Arc::clone(&server))?);
+
+    info!(
+        listen = %config.listen,
+        metrics_listen = %config.metrics_listen,
+        tls = config.tls.is_some(),
+        mtls = config.tls.as_ref().is_some_and(|tls| tls.client_ca_path.is_some()),
+        auth = config.auth.is_some(),
+        "Social Network gRPC Server listening"
+    );
+
+    // Stop accepting connections on SIGINT / SIGTERM, then give in-flight requests (including
+    // open streams) up to the drain timeout to finish
+    let signalled = Arc::new(Notify::new());
+    let shutdown = {
+        let signalled = Arc::clone(&signalled);
+        let mut health = health.clone();
+        async move {
+            shutdown_signal().await;
+            set_health(&mut health, ServingStatus::NotServing).await;
+            signalled.notify_one();
+        }
+    };
+    let mut builder = Server::builder();
+    if let Some(tls) = &config.tls {
+        builder = builder.tls_config(tls.load()?)?;
+    }
+    let serve = builder
+        .layer(RequestIdLayer)
+        .layer(MetricsLayer::new(server.metrics()))
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(social_network_service)
+        .serve_with_shutdown(config.listen, shutdown);
+    tokio::pin!(serve);
 
-    println!("Social Network gRPC Server listening on {}", addr);
+    let restore = {
+        let server = Arc::clone(&server);
+        let config = config.clone();
+        tokio::task::spawn_blocking(move || server.restore(&config))
+    };
+    tokio::select! {
+        // Shut down (or failed to start) before the replay finished
+        result = &mut serve => return Ok(result?),
+        result = restore => result??,
+    }
+    ready.store(true, Ordering::Release);
+    set_health(&mut health, ServingStatus::Serving).await;
+    info!("Ready to serve requests");
 
-    Server::builder()
-        .add_service(server)
-        .serve(addr)
-        .await?;
+    let autocommit = (config.autocommit == AutocommitPolicy::Interval).then(|| {
+        let interval = Duration::from_millis(config.autocommit_interval_ms);
+        tokio::spawn(Arc::clone(&server).autocommit_every(interval))
+    });
+
+    tokio::select! {
+        result = &mut serve => result?,
+        () = signalled.notified() => {
+            info!("Shutting down, draining in-flight requests");
+            server.close_change_feeds();
+            let drain_timeout = Duration::from_millis(config.drain_timeout_ms);
+            if let Ok(result) = tokio::time::timeout(drain_timeout, &mut serve).await {
+                result?;
+            } else {
+                warn!(?drain_timeout, "Drain timeout elapsed, abandoning in-flight requests");
+            }
+        }
+    }
+
+    if let Some(autocommit) = autocommit {
+        autocommit.abort();
+    }
+    metrics.abort();
+    server.flush()?;
+    info!("Shutdown complete");
 
     Ok(())
-} 
\ No newline at end of file
+}
+
+/// Log to stdout at the configured level and format. Dependencies only log warnings and errors.
+fn init_tracing(config: &ServerConfig) {
+    let level = LevelFilter::from(config.log_level);
+    let filter = Targets::new()
+        .with_default(level.min(LevelFilter::WARN))
+        .with_target("socialnetwork", level)
+        .with_target(env!("CARGO_BIN_NAME"), level);
+
+    let registry = tracing_subscriber::registry().with(filter);
+    match config.log_format {
+        LogFormat::Pretty => registry.with(tracing_subscriber::fmt::layer().pretty()).init(),
+        LogFormat::Json => registry
+            .with(tracing_subscriber::fmt::layer().json().with_current_span(true).with_span_list(true))
+            .init(),
+    }
+}
+
+/// Report the status of the social network service and of the server as a whole
+async fn set_health(health: &mut HealthReporter, status: ServingStatus) {
+    health
+        .set_service_status(<SocialNetworkServiceServer<SocialNetworkServer> as NamedService>::NAME, status)
+        .await;
+    health.set_service_status("", status).await;
+}
+
+/// Resolve on the first SIGINT (Ctrl+C) or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            // Without a handler, only SIGTERM can stop the server gracefully
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
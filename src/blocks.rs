@@ -0,0 +1,118 @@
+//! Versioned block relationships. A block severs the follows between two users and prevents
+//! new ones until it is lifted.
+
+use tracing::instrument;
+
+use crate::{ChangeKind, SocialNetwork};
+
+impl SocialNetwork {
+    /// Block a user, removing the follows between the two users in both directions
+    ///
+    /// Returns whether the block is new.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a user tries to block themselves, or if a follow cannot be removed.
+    #[instrument(level = "debug", skip(self), fields(version = self.version), ret)]
+    pub fn block(&mut self, blocker_id: u64, blocked_id: u64) -> Result<bool, String> {
+        if blocker_id == blocked_id {
+            return Err("Users cannot block themselves".to_string());
+        }
+        if self.blocks.is_open(blocker_id, blocked_id) {
+            return Ok(false);
+        }
+
+        // The unfollows are logged before the block, so replaying the block finds them done
+        self.unfollow(blocker_id, blocked_id)?;
+        self.unfollow(blocked_id, blocker_id)?;
+
+        self.blocks.start(blocker_id, blocked_id, self.version);
+        self.record_change(ChangeKind::Block, blocker_id, blocked_id);
+        Ok(true)
+    }
+
+    /// Lift a block. The users can follow each other again, but the follows removed by the block
+    /// are not restored.
+    ///
+    /// Returns whether there was a block to lift.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a user tries to unblock themselves.
+    #[instrument(level = "debug", skip(self), fields(version = self.version), ret)]
+    pub fn unblock(&mut self, blocker_id: u64, blocked_id: u64) -> Result<bool, String> {
+        if blocker_id == blocked_id {
+            return Err("Users cannot unblock themselves".to_string());
+        }
+        if !self.blocks.end(blocker_id, blocked_id, self.version) {
+            return Ok(false);
+        }
+        self.record_change(ChangeKind::Unblock, blocker_id, blocked_id);
+        Ok(true)
+    }
+
+    /// Check if blocker has blocked a user at a version (use current version if not specified)
+    #[must_use]
+    pub fn is_blocked(&self, blocker_id: u64, blocked_id: u64, version: Option<u64>) -> bool {
+        let version = version.unwrap_or(self.version);
+        version <= self.version && self.blocks.is_active(blocker_id, blocked_id, version)
+    }
+
+    /// Users a user has blocked at a version (use current version if not specified), sorted by user id
+    #[must_use]
+    pub fn blocked_users(&self, blocker_id: u64, version: Option<u64>) -> Vec<u64> {
+        match version.unwrap_or(self.version) {
+            version if version > self.version => Vec::new(),
+            version => self.blocks.others_at(blocker_id, version),
+        }
+    }
+
+    /// The block in force between two users, in either direction, as (blocker, blocked)
+    pub(crate) fn active_block_between(&self, user_id: u64, other_id: u64) -> Option<(u64, u64)> {
+        [(user_id, other_id), (other_id, user_id)]
+            .into_iter()
+            .find(|&(blocker_id, blocked_id)| self.blocks.is_open(blocker_id, blocked_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ChangeKind, FollowError, SocialNetwork};
+
+    #[test]
+    fn test_block_and_unblock() {
+        let mut network = SocialNetwork::new();
+        network.follow(1, 2).unwrap();
+        network.follow(2, 1).unwrap();
+        network.follow(1, 3).unwrap();
+        let v1 = network.commit();
+
+        // Blocking severs the follows in both directions
+        assert!(network.block(1, 2).unwrap());
+        assert!(!network.block(1, 2).unwrap());
+        let v2 = network.commit();
+        assert!(!network.is_following(1, 2, Some(v2)) && !network.is_following(2, 1, Some(v2)));
+        assert_eq!(network.get_followees(1), vec![3]);
+        assert_eq!(network.follow(2, 1), Err(FollowError::Blocked { blocker_id: 1, blocked_id: 2 }));
+        assert_eq!(network.follow(1, 2), Err(FollowError::Blocked { blocker_id: 1, blocked_id: 2 }));
+        assert!(network.block(1, 1).is_err());
+
+        let kinds: Vec<ChangeKind> = network.changes_since(v1).iter().map(|change| change.kind).collect();
+        assert_eq!(kinds, vec![ChangeKind::Unfollow, ChangeKind::Unfollow, ChangeKind::Block, ChangeKind::Commit]);
+
+        // Lifting the block allows new follows but does not restore the old ones
+        assert!(network.unblock(1, 2).unwrap());
+        assert!(!network.unblock(1, 2).unwrap());
+        assert!(!network.is_following(2, 1, None));
+        assert!(network.follow(2, 1).unwrap());
+        let v3 = network.commit();
+
+        // The block history is kept for time-travel queries
+        assert!(!network.is_blocked(1, 2, Some(v1 - 1)));
+        assert!(network.is_blocked(1, 2, Some(v1)) && network.is_blocked(1, 2, Some(v2)));
+        assert!(!network.is_blocked(1, 2, Some(v3)));
+        assert!(!network.is_blocked(2, 1, Some(v2)));
+        assert_eq!(network.blocked_users(1, Some(v2)), vec![2]);
+        assert!(network.blocked_users(1, None).is_empty());
+    }
+}
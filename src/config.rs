@@ -0,0 +1,360 @@
+//! Server configuration, loaded from a TOML file and overridden by command line flags.
+
+use std::net::SocketAddr;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing::level_filters::LevelFilter;
+
+use crate::auth::{AuthConfig, RolePolicy};
+use crate::rate_limit::RateLimits;
+use crate::policy::{BannedUsers, FollowBackOnly, MaxFollowees, UnfollowCooldown};
+use crate::tls::ServerTls;
+use crate::{FollowQuota, SocialNetwork};
+
+/// Where the server keeps the follow graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PersistenceMode {
+    /// Keep everything in memory; the graph is lost on restart
+    #[default]
+    Memory,
+    /// Append every change to a log in the data directory and replay it on startup
+    Log,
+}
+
+/// When the server commits without an explicit `Commit` request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum AutocommitPolicy {
+    /// Only commit on `Commit` (or `BatchMutate` with `commit` set)
+    #[default]
+    Manual,
+    /// Commit after every request that changes the graph; a batch commits once
+    EveryMutation,
+    /// Commit pending changes every `autocommit_interval_ms`
+    Interval,
+}
+
+/// Verbosity of server logging, from least to most verbose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Self::ERROR,
+            LogLevel::Warn => Self::WARN,
+            LogLevel::Info => Self::INFO,
+            LogLevel::Debug => Self::DEBUG,
+            LogLevel::Trace => Self::TRACE,
+        }
+    }
+}
+
+/// Format of server log output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Multi-line, human readable output
+    #[default]
+    Pretty,
+    /// One JSON object per line, including the fields of the enclosing spans
+    Json,
+}
+
+/// Request size limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Page size used when a listing request does not specify one
+    pub default_page_size: usize,
+
+    /// Upper bound on the page size of a listing request, keeping responses well below message size limits
+    pub max_page_size: usize,
+
+    /// Maximum number of items (mutations, queries or candidates) in a single batch request
+    pub max_batch_size: usize,
+
+    /// Number of idempotency keys whose responses are remembered for retries
+    pub idempotency_keys: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            default_page_size: 1_000,
+            max_page_size: 10_000,
+            max_batch_size: 10_000,
+            idempotency_keys: 10_000,
+        }
+    }
+}
+
+/// Limit on the new follows each user can make, enforced by the graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FollowQuotaConfig {
+    pub max_follows: usize,
+
+    /// Length of the sliding window, a day unless set
+    #[serde(default = "FollowQuotaConfig::default_window_secs")]
+    pub window_secs: u64,
+}
+
+impl FollowQuotaConfig {
+    const fn default_window_secs() -> u64 {
+        24 * 60 * 60
+    }
+}
+
+impl From<FollowQuotaConfig> for FollowQuota {
+    fn from(config: FollowQuotaConfig) -> Self {
+        Self { max_follows: config.max_follows, window: Duration::from_secs(config.window_secs) }
+    }
+}
+
+/// Follow policies enforced by the graph; unset rules are not enforced
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FollowPolicyConfig {
+    /// Most users anyone can follow at once
+    pub max_followees: Option<u64>,
+
+    /// Users who can neither follow nor be followed
+    pub banned_users: HashSet<u64>,
+
+    /// Accounts that can only be followed by users they already follow
+    pub follow_back_only: HashSet<u64>,
+
+    /// Versions that must pass after an unfollow before the same follow can be made again
+    pub unfollow_cooldown_versions: Option<u64>,
+}
+
+impl FollowPolicyConfig {
+    /// Add the configured policies to `network`
+    pub fn apply(&self, network: &mut SocialNetwork) {
+        if let Some(max_followees) = self.max_followees {
+            network.add_follow_policy(MaxFollowees(max_followees));
+        }
+        if !self.banned_users.is_empty() {
+            network.add_follow_policy(BannedUsers(self.banned_users.clone()));
+        }
+        if !self.follow_back_only.is_empty() {
+            network.add_follow_policy(FollowBackOnly(self.follow_back_only.clone()));
+        }
+        if let Some(cooldown) = self.unfollow_cooldown_versions {
+            network.add_follow_policy(UnfollowCooldown(cooldown));
+        }
+    }
+}
+
+/// Configuration of the gRPC server. Every field has a default, so a config file only needs
+/// the settings it changes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the gRPC server listens on
+    pub listen: SocketAddr,
+
+    /// Address of the HTTP server exposing Prometheus metrics at `/metrics`
+    pub metrics_listen: SocketAddr,
+
+    /// Directory holding persisted data
+    pub data_dir: PathBuf,
+
+    pub persistence: PersistenceMode,
+    pub autocommit: AutocommitPolicy,
+
+    /// Time between commits with the `interval` autocommit policy
+    pub autocommit_interval_ms: u64,
+
+    /// How long a shutdown waits for in-flight requests before abandoning them
+    pub drain_timeout_ms: u64,
+
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    pub limits: Limits,
+
+    /// Serve over TLS instead of plaintext
+    pub tls: Option<ServerTls>,
+
+    /// Require a bearer token on every social network RPC
+    pub auth: Option<AuthConfig>,
+
+    /// Request rates allowed per caller, or per peer address without authentication
+    pub rate_limits: RateLimits,
+
+    pub follow_quota: Option<FollowQuotaConfig>,
+    pub follow_policy: FollowPolicyConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 50051)),
+            metrics_listen: SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 9090)),
+            data_dir: PathBuf::from("data"),
+            persistence: PersistenceMode::default(),
+            autocommit: AutocommitPolicy::default(),
+            autocommit_interval_ms: 1_000,
+            drain_timeout_ms: 10_000,
+            log_level: LogLevel::default(),
+            log_format: LogFormat::default(),
+            limits: Limits::default(),
+            tls: None,
+            auth: None,
+            rate_limits: RateLimits::default(),
+            follow_quota: None,
+            follow_policy: FollowPolicyConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Load and validate a config file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, is not valid TOML or fails validation.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {e}", path.display()))?;
+        Self::from_toml(&contents).map_err(|e| format!("Invalid config file {}: {e}", path.display()))
+    }
+
+    /// Parse and validate a TOML config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the config is not valid TOML, has unknown keys or fails validation.
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(contents).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that the settings are consistent
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first inconsistent setting.
+    pub fn validate(&self) -> Result<(), String> {
+        let limits = &self.limits;
+        if limits.max_page_size == 0 || limits.max_batch_size == 0 {
+            return Err("Page and batch size limits must be positive".to_string());
+        }
+        if limits.default_page_size == 0 || limits.default_page_size > limits.max_page_size {
+            return Err(format!(
+                "default_page_size must be between 1 and max_page_size ({})",
+                limits.max_page_size
+            ));
+        }
+        if self.metrics_listen == self.listen {
+            return Err("metrics_listen must differ from listen".to_string());
+        }
+        if self.autocommit == AutocommitPolicy::Interval && self.autocommit_interval_ms == 0 {
+            return Err("autocommit_interval_ms must be positive with the interval autocommit policy".to_string());
+        }
+        if let Some(auth) = &self.auth {
+            RolePolicy::new(&auth.roles)?;
+        }
+        self.rate_limits.validate()?;
+        if self.follow_quota.is_some_and(|quota| quota.window_secs == 0) {
+            return Err("follow_quota.window_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Role;
+    use crate::rate_limit::RateLimit;
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(ServerConfig::from_toml("").unwrap(), ServerConfig::default());
+
+        let config = ServerConfig::from_toml(
+            r#"
+            listen = "127.0.0.1:6000"
+            metrics_listen = "127.0.0.1:6001"
+            data_dir = "/var/lib/socialnetwork"
+            persistence = "log"
+            autocommit = "interval"
+            autocommit_interval_ms = 250
+            drain_timeout_ms = 500
+            log_level = "debug"
+            log_format = "json"
+
+            [limits]
+            max_batch_size = 500
+
+            [tls]
+            cert_path = "server.pem"
+            key_path = "server.key"
+
+            [auth]
+            jwt_secret_file = "jwt.secret"
+
+            [auth.roles]
+            Commit = "writer"
+
+            [rate_limits]
+            writes = { per_second = 100, burst = 200 }
+
+            [follow_quota]
+            max_follows = 400
+
+            [follow_policy]
+            max_followees = 5000
+            banned_users = [13]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.listen, "127.0.0.1:6000".parse().unwrap());
+        assert_eq!(config.metrics_listen, "127.0.0.1:6001".parse().unwrap());
+        assert_eq!(config.data_dir, PathBuf::from("/var/lib/socialnetwork"));
+        assert_eq!(config.persistence, PersistenceMode::Log);
+        assert_eq!(config.autocommit, AutocommitPolicy::Interval);
+        assert_eq!(config.autocommit_interval_ms, 250);
+        assert_eq!(config.drain_timeout_ms, 500);
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.limits.max_batch_size, 500);
+        assert_eq!(config.limits.max_page_size, Limits::default().max_page_size);
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.cert_path, PathBuf::from("server.pem"));
+        assert_eq!(tls.client_ca_path, None);
+        let auth = config.auth.unwrap();
+        assert_eq!(auth.jwt_secret_file, Some(PathBuf::from("jwt.secret")));
+        assert_eq!(auth.token_file, None);
+        assert_eq!(auth.roles.get("Commit"), Some(&Role::Writer));
+        assert_eq!(config.rate_limits.writes, Some(RateLimit { per_second: 100, burst: 200 }));
+        assert_eq!(config.rate_limits.reads, None);
+        let quota = FollowQuota::from(config.follow_quota.unwrap());
+        assert_eq!(config.follow_policy.max_followees, Some(5000));
+        assert_eq!(config.follow_policy.banned_users, HashSet::from([13]));
+        assert_eq!(config.follow_policy.unfollow_cooldown_versions, None);
+        assert_eq!(quota, FollowQuota { max_follows: 400, window: Duration::from_secs(FollowQuotaConfig::default_window_secs()) });
+
+        assert!(ServerConfig::from_toml("port = 1").is_err());
+        assert!(ServerConfig::from_toml("persistence = \"disk\"").is_err());
+        assert!(ServerConfig::from_toml("[limits]\ndefault_page_size = 20000").is_err());
+        assert!(ServerConfig::from_toml("[tls]\ncert_path = \"server.pem\"").is_err());
+        assert!(ServerConfig::from_toml("[auth.roles]\nCompact = \"admin\"").is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Bounded memory of recent responses keyed by client-supplied idempotency keys.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Remembers the responses to the most recent `capacity` keyed requests, forgetting the oldest
+/// key first, so a retried request can be answered with its original response.
+#[derive(Debug)]
+pub struct IdempotencyCache<K, V> {
+    capacity: usize,
+    responses: HashMap<K, V>,
+
+    /// Keys in insertion order, oldest first
+    order: VecDeque<K>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> IdempotencyCache<K, V> {
+    /// Create a cache remembering at most `capacity` keys
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            responses: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Get the response remembered for a key
+    #[must_use]
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.responses.get(key).cloned()
+    }
+
+    /// Remember the response for a key, evicting the oldest key if the cache is full
+    pub fn insert(&mut self, key: K, response: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.responses.insert(key.clone(), response).is_some() {
+            return;
+        }
+
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.responses.remove(&oldest);
+            }
+        }
+    }
+
+    /// Number of remembered keys
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.responses.len()
+    }
+
+    /// Whether no keys are remembered
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.responses.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idempotency_cache_evicts_oldest() {
+        let mut cache = IdempotencyCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        cache.insert("a", 3); // already remembered, keeps its slot
+        assert_eq!(cache.get(&"a"), Some(3));
+
+        cache.insert("c", 4);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.get(&"b"), Some(2));
+        assert_eq!(cache.get(&"c"), Some(4));
+    }
+}
@@ -0,0 +1,295 @@
+//! Prometheus metrics: per-RPC request counts, errors and latency, network lock wait time and
+//! graph size, served over HTTP at `/metrics`.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, StatusCode};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use tonic::Code;
+
+use crate::server::SocialNetworkServer;
+use crate::SocialNetwork;
+
+/// Upper bounds of the lock wait histogram buckets, from 1µs to 1s
+const LOCK_WAIT_BUCKETS: [f64; 7] = [1e-6, 1e-5, 1e-4, 1e-3, 1e-2, 1e-1, 1.0];
+
+/// Side of the network lock a caller waited for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Read,
+    Write,
+}
+
+impl LockMode {
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+        }
+    }
+}
+
+/// Metrics of a social network server, registered in their own registry
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+
+    /// Requests by service, method and gRPC status code
+    requests: IntCounterVec,
+
+    /// Requests that did not complete with `OK`, by service, method and gRPC status code
+    errors: IntCounterVec,
+
+    /// Time to respond by service and method. For streaming RPCs this is the time until the
+    /// stream was opened.
+    latency: HistogramVec,
+
+    lock_wait: HistogramVec,
+    version: IntGauge,
+    users: IntGauge,
+    edges: IntGauge,
+    intervals: IntGauge,
+}
+
+impl Metrics {
+    /// Create and register every metric
+    ///
+    /// # Panics
+    ///
+    /// Never panics: the metric names are fixed and unique within the fresh registry.
+    #[must_use]
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("socialnetwork".to_string()), None)
+            .expect("The registry prefix is valid");
+        let metrics = Self {
+            requests: IntCounterVec::new(
+                Opts::new("rpc_requests_total", "gRPC requests handled"),
+                &["service", "method", "code"],
+            )
+            .expect("Metric options are valid"),
+            errors: IntCounterVec::new(
+                Opts::new("rpc_errors_total", "gRPC requests that failed"),
+                &["service", "method", "code"],
+            )
+            .expect("Metric options are valid"),
+            latency: HistogramVec::new(
+                HistogramOpts::new("rpc_duration_seconds", "Time to handle a gRPC request"),
+                &["service", "method"],
+            )
+            .expect("Metric options are valid"),
+            lock_wait: HistogramVec::new(
+                HistogramOpts::new("lock_wait_seconds", "Time spent waiting for the network lock")
+                    .buckets(LOCK_WAIT_BUCKETS.to_vec()),
+                &["mode"],
+            )
+            .expect("Metric options are valid"),
+            version: IntGauge::new("version", "Current version of the graph").expect("Metric options are valid"),
+            users: IntGauge::new("users", "Users who have ever followed or been followed")
+                .expect("Metric options are valid"),
+            edges: IntGauge::new("edges", "Follows currently active").expect("Metric options are valid"),
+            intervals: IntGauge::new("follow_intervals", "Follow intervals stored for the version history")
+                .expect("Metric options are valid"),
+            registry,
+        };
+
+        for collector in [
+            Box::new(metrics.requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.errors.clone()),
+            Box::new(metrics.latency.clone()),
+            Box::new(metrics.lock_wait.clone()),
+            Box::new(metrics.version.clone()),
+            Box::new(metrics.users.clone()),
+            Box::new(metrics.edges.clone()),
+            Box::new(metrics.intervals.clone()),
+        ] {
+            metrics.registry.register(collector).expect("Metric names are unique");
+        }
+        metrics
+    }
+
+    /// Record a handled request by its HTTP path (`/package.Service/Method`)
+    pub fn observe_request(&self, path: &str, code: Code, elapsed: Duration) {
+        let (service, method) = path.trim_start_matches('/').split_once('/').unwrap_or(("", path));
+        let code_label = format!("{code:?}");
+
+        self.requests.with_label_values(&[service, method, &code_label]).inc();
+        if code != Code::Ok {
+            self.errors.with_label_values(&[service, method, &code_label]).inc();
+        }
+        self.latency.with_label_values(&[service, method]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Record how long it took to acquire the network lock
+    pub fn observe_lock_wait(&self, mode: LockMode, elapsed: Duration) {
+        self.lock_wait.with_label_values(&[mode.label()]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Update the graph size gauges
+    pub fn observe_graph(&self, network: &SocialNetwork) {
+        let gauge = |value: u64| i64::try_from(value).unwrap_or(i64::MAX);
+        self.version.set(gauge(network.current_version()));
+        self.users.set(gauge(network.user_count()));
+        self.edges.set(gauge(network.edge_count()));
+        self.intervals.set(gauge(network.interval_count()));
+    }
+
+    /// Encode every metric in the Prometheus text format
+    #[must_use]
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        // Encoding into memory cannot fail
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tower layer recording request counts, errors and latency for every gRPC request
+#[derive(Debug, Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsLayer {
+    #[must_use]
+    pub const fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> tower::Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService { inner, metrics: Arc::clone(&self.metrics) }
+    }
+}
+
+/// Service wrapper created by [`MetricsLayer`]
+#[derive(Debug, Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, ReqBody, ResBody> tower::Service<http::Request<ReqBody>> for MetricsService<S>
+where
+    S: tower::Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let path = request.uri().path().to_string();
+        let metrics = Arc::clone(&self.metrics);
+        let started = Instant::now();
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let result = response.await;
+            metrics.observe_request(&path, grpc_code(&result), started.elapsed());
+            result
+        })
+    }
+}
+
+/// Status code of a gRPC response. Failures are sent as trailers-only responses, so the status is
+/// in the headers; successful responses carry it in the trailers instead.
+pub(crate) fn grpc_code<B, E>(result: &Result<http::Response<B>, E>) -> Code {
+    result.as_ref().map_or(Code::Unknown, |response| {
+        response
+            .headers()
+            .get("grpc-status")
+            .and_then(|status| status.to_str().ok())
+            .and_then(|status| status.parse::<i32>().ok())
+            .map_or(Code::Ok, Code::from)
+    })
+}
+
+/// Bind an HTTP server answering `GET /metrics` with the server's metrics
+///
+/// # Errors
+///
+/// Returns an error if the address cannot be bound.
+pub fn metrics_server(
+    listen: SocketAddr,
+    server: Arc<SocialNetworkServer>,
+) -> Result<impl Future<Output = Result<(), hyper::Error>>, hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let server = Arc::clone(&server);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let server = Arc::clone(&server);
+                async move { Ok::<_, Infallible>(metrics_response(&server, &request)) }
+            }))
+        }
+    });
+
+    Ok(hyper::Server::try_bind(&listen)?.serve(make_service))
+}
+
+fn metrics_response(server: &SocialNetworkServer, request: &hyper::Request<Body>) -> hyper::Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        let mut response = hyper::Response::new(Body::from("Not found"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let mut response = hyper::Response::new(Body::from(server.encode_metrics()));
+    if let Ok(content_type) = TextEncoder::new().format_type().parse() {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_encoding() {
+        let metrics = Metrics::new();
+        let path = "/social_network.SocialNetworkService/Follow";
+        metrics.observe_request(path, Code::Ok, Duration::from_millis(2));
+        metrics.observe_request(path, Code::FailedPrecondition, Duration::from_millis(1));
+        metrics.observe_lock_wait(LockMode::Write, Duration::from_micros(5));
+
+        let mut network = SocialNetwork::new();
+        network.follow(1, 2).unwrap();
+        network.commit();
+        metrics.observe_graph(&network);
+
+        let text = metrics.encode();
+        assert!(text.contains(
+            "socialnetwork_rpc_requests_total{code=\"Ok\",method=\"Follow\",service=\"social_network.SocialNetworkService\"} 1"
+        ));
+        assert!(text.contains(
+            "socialnetwork_rpc_errors_total{code=\"FailedPrecondition\",method=\"Follow\",service=\"social_network.SocialNetworkService\"} 1"
+        ));
+        assert!(text.contains("socialnetwork_rpc_duration_seconds_count{method=\"Follow\""));
+        assert!(text.contains("socialnetwork_lock_wait_seconds_count{mode=\"write\"} 1"));
+        assert!(text.contains("socialnetwork_version 1"));
+        assert!(text.contains("socialnetwork_users 2"));
+        assert!(text.contains("socialnetwork_edges 1"));
+        assert!(text.contains("socialnetwork_follow_intervals 1"));
+    }
+}
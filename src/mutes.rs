@@ -0,0 +1,112 @@
+//! Versioned mute relationships. A muter stops seeing the muted user in their feed without
+//! unfollowing them, so the follow graph and follower counts are unaffected.
+
+use tracing::instrument;
+
+use crate::{ChangeKind, SocialNetwork};
+
+impl SocialNetwork {
+    /// Mute a user. Follows in either direction are left as they are.
+    ///
+    /// Returns whether the mute is new.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a user tries to mute themselves.
+    #[instrument(level = "debug", skip(self), fields(version = self.version), ret)]
+    pub fn mute(&mut self, muter_id: u64, muted_id: u64) -> Result<bool, String> {
+        if muter_id == muted_id {
+            return Err("Users cannot mute themselves".to_string());
+        }
+        if !self.mutes.start(muter_id, muted_id, self.version) {
+            return Ok(false);
+        }
+        self.record_change(ChangeKind::Mute, muter_id, muted_id);
+        Ok(true)
+    }
+
+    /// Lift a mute
+    ///
+    /// Returns whether there was a mute to lift.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a user tries to unmute themselves.
+    #[instrument(level = "debug", skip(self), fields(version = self.version), ret)]
+    pub fn unmute(&mut self, muter_id: u64, muted_id: u64) -> Result<bool, String> {
+        if muter_id == muted_id {
+            return Err("Users cannot unmute themselves".to_string());
+        }
+        if !self.mutes.end(muter_id, muted_id, self.version) {
+            return Ok(false);
+        }
+        self.record_change(ChangeKind::Unmute, muter_id, muted_id);
+        Ok(true)
+    }
+
+    /// Check if muter has muted a user at a version (use current version if not specified)
+    #[must_use]
+    pub fn is_muted(&self, muter_id: u64, muted_id: u64, version: Option<u64>) -> bool {
+        let version = version.unwrap_or(self.version);
+        version <= self.version && self.mutes.is_active(muter_id, muted_id, version)
+    }
+
+    /// Users a user has muted at a version (use current version if not specified), sorted by user id
+    #[must_use]
+    pub fn muted_users(&self, muter_id: u64, version: Option<u64>) -> Vec<u64> {
+        match version.unwrap_or(self.version) {
+            version if version > self.version => Vec::new(),
+            version => self.mutes.others_at(muter_id, version),
+        }
+    }
+
+    /// Followees whose posts belong in a user's feed at a version (use current version if not
+    /// specified): everyone they follow except those they have muted, sorted by user id
+    #[must_use]
+    pub fn feed_followees(&self, user_id: u64, version: Option<u64>) -> Vec<u64> {
+        let mut followees = self.followees_at(user_id, version);
+        followees.retain(|&followee_id| !self.is_muted(user_id, followee_id, version));
+        followees.sort_unstable();
+        followees
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SocialNetwork;
+
+    #[test]
+    fn test_mute_and_unmute() {
+        let mut network = SocialNetwork::new();
+        network.follow(1, 2).unwrap();
+        network.follow(1, 3).unwrap();
+        network.follow(2, 1).unwrap();
+        let v1 = network.commit();
+
+        assert!(network.mute(1, 2).unwrap());
+        assert!(!network.mute(1, 2).unwrap());
+        assert!(network.mute(1, 1).is_err());
+        let v2 = network.commit();
+
+        // Muting hides the followee from the feed but leaves the follow graph alone
+        assert_eq!(network.feed_followees(1, None), vec![3]);
+        assert_eq!(network.feed_followees(1, Some(v1 - 1)), vec![2, 3]);
+        assert!(network.is_following(1, 2, None) && network.is_following(2, 1, None));
+        assert_eq!(network.follower_count(2, None), 1);
+        assert!(!network.follow(1, 2).unwrap());
+
+        // Users can be muted without being followed
+        assert!(network.mute(1, 4).unwrap());
+        assert!(network.unmute(1, 2).unwrap());
+        assert!(!network.unmute(1, 2).unwrap());
+        let v3 = network.commit();
+
+        assert!(!network.is_muted(1, 2, Some(v1 - 1)));
+        assert!(network.is_muted(1, 2, Some(v1)) && network.is_muted(1, 2, Some(v2)));
+        assert!(!network.is_muted(1, 2, Some(v3)));
+        assert!(!network.is_muted(2, 1, Some(v2)));
+        assert_eq!(network.muted_users(1, Some(v2)), vec![2, 4]);
+        assert_eq!(network.muted_users(1, None), vec![4]);
+        assert_eq!(network.feed_followees(1, None), vec![2, 3]);
+    }
+}
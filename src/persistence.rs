@@ -0,0 +1,171 @@
+//! Durable change log: every change log entry (follows, blocks, mutes, commits and their
+//! reversals) is appended to a file as a JSON line and replayed on startup to rebuild the graph.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{ChangeEvent, ChangeKind, SocialNetwork};
+
+/// Name of the change log file inside the data directory
+pub const CHANGE_LOG_FILE: &str = "changes.log";
+
+/// Append-only file mirroring [`SocialNetwork::changes`]
+#[derive(Debug)]
+pub struct ChangeLogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+
+    /// Number of changes already written to the file
+    persisted: usize,
+}
+
+impl ChangeLogFile {
+    /// Open (or create) the change log in `data_dir` and replay it into a new network.
+    ///
+    /// A partially written last line, left behind by a crash mid-append, is discarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or written, or if an entry does not
+    /// match the change it produces on replay.
+    pub fn open(data_dir: &Path) -> Result<(Self, SocialNetwork), String> {
+        std::fs::create_dir_all(data_dir)
+            .map_err(|e| format!("Failed to create data directory {}: {e}", data_dir.display()))?;
+        let path = data_dir.join(CHANGE_LOG_FILE);
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open change log {}: {e}", path.display()))?;
+
+        let mut network = SocialNetwork::new();
+        let mut valid_len = 0;
+        let mut lines = BufReader::new(&file).split(b'\n').peekable();
+        while let Some(line) = lines.next() {
+            let line = line.map_err(|e| format!("Failed to read change log {}: {e}", path.display()))?;
+            let Ok(change) = serde_json::from_slice::<ChangeEvent>(&line) else {
+                if lines.peek().is_none() {
+                    break;
+                }
+                return Err(format!("Corrupt change log {} at entry {}", path.display(), network.changes().len()));
+            };
+            replay(&mut network, &change)
+                .map_err(|e| format!("Corrupt change log {} at entry {}: {e}", path.display(), change.sequence))?;
+            valid_len += line.len() as u64 + 1;
+        }
+        file.set_len(valid_len)
+            .map_err(|e| format!("Failed to truncate change log {}: {e}", path.display()))?;
+
+        let persisted = network.changes().len();
+        Ok((Self { path, writer: BufWriter::new(file), persisted }, network))
+    }
+
+    /// Append the changes made since the last call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn append(&mut self, network: &SocialNetwork) -> Result<(), String> {
+        for change in &network.changes()[self.persisted..] {
+            serde_json::to_writer(&mut self.writer, change)
+                .map_err(|e| format!("Failed to write change log {}: {e}", self.path.display()))?;
+            self.writer
+                .write_all(b"\n")
+                .map_err(|e| format!("Failed to write change log {}: {e}", self.path.display()))?;
+        }
+        self.writer
+            .flush()
+            .map_err(|e| format!("Failed to write change log {}: {e}", self.path.display()))?;
+        self.persisted = network.changes().len();
+        Ok(())
+    }
+
+    /// Wait until everything appended so far is on disk
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be synced.
+    pub fn sync(&mut self) -> Result<(), String> {
+        self.writer
+            .flush()
+            .and_then(|()| self.writer.get_ref().sync_all())
+            .map_err(|e| format!("Failed to sync change log {}: {e}", self.path.display()))
+    }
+}
+
+/// Apply a logged change and check it reproduces the same entry
+fn replay(network: &mut SocialNetwork, change: &ChangeEvent) -> Result<(), String> {
+    match change.kind {
+        ChangeKind::Follow => {
+            network.follow(change.follower_id, change.followee_id)?;
+        }
+        ChangeKind::Unfollow => {
+            network.unfollow(change.follower_id, change.followee_id)?;
+        }
+        ChangeKind::Block => {
+            network.block(change.follower_id, change.followee_id)?;
+        }
+        ChangeKind::Unblock => {
+            network.unblock(change.follower_id, change.followee_id)?;
+        }
+        ChangeKind::Mute => {
+            network.mute(change.follower_id, change.followee_id)?;
+        }
+        ChangeKind::Unmute => {
+            network.unmute(change.follower_id, change.followee_id)?;
+        }
+        ChangeKind::Commit => {
+            network.commit();
+        }
+    }
+    if network.changes().last() == Some(change) {
+        Ok(())
+    } else {
+        Err(format!("Replaying {change:?} did not reproduce it"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("socialnetwork-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_change_log_replay() {
+        let dir = data_dir("replay");
+
+        let (mut log, mut network) = ChangeLogFile::open(&dir).unwrap();
+        network.follow(1, 2).unwrap();
+        network.follow(1, 3).unwrap();
+        let v1 = network.commit();
+        network.unfollow(1, 2).unwrap();
+        log.append(&network).unwrap();
+        drop(log);
+
+        // Simulate a crash in the middle of an append
+        let path = dir.join(CHANGE_LOG_FILE);
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"sequence\":4,").unwrap();
+
+        let (mut log, mut replayed) = ChangeLogFile::open(&dir).unwrap();
+        assert_eq!(replayed.changes(), network.changes());
+        assert_eq!(replayed.current_version(), v1);
+        assert!(replayed.is_following(1, 2, Some(v1 - 1)));
+        assert_eq!(replayed.get_followees(1), vec![3]);
+
+        // The torn entry was dropped, so new entries follow on cleanly
+        replayed.commit();
+        log.append(&replayed).unwrap();
+        drop(log);
+        let (_, reopened) = ChangeLogFile::open(&dir).unwrap();
+        assert_eq!(reopened.changes(), replayed.changes());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+//! Product rules deciding whether a follow may happen, consulted by [`SocialNetwork::follow`].
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+
+use crate::SocialNetwork;
+
+/// A rule checked before a follow creates a new edge. Following someone already followed never
+/// consults the policies.
+pub trait FollowPolicy: Debug + Send + Sync {
+    /// Check whether `follower_id` may start following `followee_id` in `network`
+    ///
+    /// # Errors
+    ///
+    /// Returns a message explaining why the follow is not allowed.
+    fn check(&self, network: &SocialNetwork, follower_id: u64, followee_id: u64) -> Result<(), String>;
+}
+
+/// Cap on how many users anyone can follow at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxFollowees(pub u64);
+
+impl FollowPolicy for MaxFollowees {
+    fn check(&self, network: &SocialNetwork, follower_id: u64, _followee_id: u64) -> Result<(), String> {
+        if network.followee_count(follower_id, None) >= self.0 {
+            return Err(format!("User {follower_id} already follows the maximum of {} users", self.0));
+        }
+        Ok(())
+    }
+}
+
+/// Users who can neither follow nor be followed
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BannedUsers(pub HashSet<u64>);
+
+impl FollowPolicy for BannedUsers {
+    fn check(&self, _network: &SocialNetwork, follower_id: u64, followee_id: u64) -> Result<(), String> {
+        [follower_id, followee_id]
+            .into_iter()
+            .find(|user_id| self.0.contains(user_id))
+            .map_or(Ok(()), |user_id| Err(format!("User {user_id} is banned")))
+    }
+}
+
+/// Accounts that can only be followed by users they already follow
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FollowBackOnly(pub HashSet<u64>);
+
+impl FollowPolicy for FollowBackOnly {
+    fn check(&self, network: &SocialNetwork, follower_id: u64, followee_id: u64) -> Result<(), String> {
+        if self.0.contains(&followee_id) && !network.follows_now(followee_id, follower_id) {
+            return Err(format!("User {followee_id} can only be followed by users they follow"));
+        }
+        Ok(())
+    }
+}
+
+/// Number of versions that must pass after an unfollow before the same follow can be made again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnfollowCooldown(pub u64);
+
+impl FollowPolicy for UnfollowCooldown {
+    fn check(&self, network: &SocialNetwork, follower_id: u64, followee_id: u64) -> Result<(), String> {
+        let Some(unfollowed_at) = network.last_unfollow_version(follower_id, followee_id) else {
+            return Ok(());
+        };
+        let available_at = unfollowed_at.saturating_add(self.0);
+        if network.current_version() < available_at {
+            return Err(format!(
+                "User {follower_id} unfollowed {followee_id} at version {unfollowed_at} and cannot follow again before version {available_at}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follow_policies() {
+        let mut network = SocialNetwork::new();
+        network.add_follow_policy(MaxFollowees(2));
+        network.add_follow_policy(BannedUsers(HashSet::from([66])));
+        network.add_follow_policy(FollowBackOnly(HashSet::from([100])));
+        network.add_follow_policy(UnfollowCooldown(2));
+
+        assert!(network.follow(1, 2).unwrap());
+        assert!(network.follow(1, 3).unwrap());
+        assert!(network.follow(1, 4).unwrap_err().to_string().contains("maximum"));
+        // Re-following an existing followee is not a new follow
+        assert!(!network.follow(1, 3).unwrap());
+
+        assert!(network.follow(66, 2).is_err());
+        assert!(network.follow(2, 66).is_err());
+
+        assert!(network.follow(5, 100).is_err());
+        network.follow(100, 5).unwrap();
+        assert!(network.follow(5, 100).unwrap());
+        // Only the follow back in force right now counts
+        network.unfollow(100, 5).unwrap();
+        network.unfollow(5, 100).unwrap();
+        assert!(network.follow(5, 100).is_err());
+
+        // The cooldown counts versions from the unfollow
+        network.unfollow(1, 2).unwrap();
+        network.commit();
+        assert!(network.follow(1, 2).unwrap_err().to_string().contains("cannot follow again before version 2"));
+        network.commit();
+        assert!(network.follow(1, 2).unwrap());
+        assert!(network.follow(7, 8).unwrap());
+    }
+}
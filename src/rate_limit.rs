@@ -0,0 +1,204 @@
+//! Token bucket rate limiting of reads, writes and commits, per caller identity (or peer address
+//! when authentication is disabled).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// Number of buckets kept before idle ones are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Bucket refilled at `per_second` requests, holding up to `burst`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+/// Limits for each class of request. Classes without a limit are not rate limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub reads: Option<RateLimit>,
+    pub writes: Option<RateLimit>,
+    pub commits: Option<RateLimit>,
+}
+
+impl RateLimits {
+    /// Check that every limit admits at least one request
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first limit with a zero rate or burst.
+    pub fn validate(&self) -> Result<(), String> {
+        for class in [RequestClass::Read, RequestClass::Write, RequestClass::Commit] {
+            if self.limit(class).is_some_and(|limit| limit.per_second == 0 || limit.burst == 0) {
+                return Err(format!("The {class} rate limit must have a positive per_second and burst"));
+            }
+        }
+        Ok(())
+    }
+
+    const fn limit(&self, class: RequestClass) -> Option<RateLimit> {
+        match class {
+            RequestClass::Read => self.reads,
+            RequestClass::Write => self.writes,
+            RequestClass::Commit => self.commits,
+        }
+    }
+}
+
+/// Which rate limit a request counts against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestClass {
+    Read,
+    Write,
+    Commit,
+}
+
+impl RequestClass {
+    /// Class of an RPC, by method name
+    #[must_use]
+    pub fn of(method: &str) -> Self {
+        match method {
+            "Commit" => Self::Commit,
+            "Follow" | "Unfollow" | "BatchMutate" | "Block" | "Unblock" | "Mute" | "Unmute" => Self::Write,
+            _ => Self::Read,
+        }
+    }
+}
+
+impl fmt::Display for RequestClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Commit => "commit",
+        })
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Add the tokens accrued since the last update
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let accrued = now.duration_since(self.updated).as_secs_f64() * f64::from(limit.per_second);
+        self.tokens = (self.tokens + accrued).min(f64::from(limit.burst));
+        self.updated = now;
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<(RequestClass, String), TokenBucket>,
+
+    /// Size at which idle buckets are next dropped
+    prune_at: usize,
+}
+
+/// Token buckets for every caller and request class
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(limits: RateLimits) -> Self {
+        Self { limits, buckets: Mutex::new(Buckets { buckets: HashMap::new(), prune_at: PRUNE_THRESHOLD }) }
+    }
+
+    /// Take a token from `key`'s bucket for `class`
+    ///
+    /// # Errors
+    ///
+    /// Returns how long until a token is available if the bucket is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket lock is poisoned.
+    pub fn check(&self, class: RequestClass, key: &str) -> Result<(), Duration> {
+        let Some(limit) = self.limits.limit(class) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.buckets.len() >= buckets.prune_at {
+            buckets.prune(&self.limits, now);
+        }
+
+        let bucket = buckets
+            .buckets
+            .entry((class, key.to_string()))
+            .or_insert_with(|| TokenBucket { tokens: f64::from(limit.burst), updated: now });
+        bucket.refill(limit, now);
+        let result = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / f64::from(limit.per_second)))
+        };
+        drop(buckets);
+        result
+    }
+}
+
+impl Buckets {
+    /// Drop full buckets: they behave exactly like the new bucket that would replace them
+    fn prune(&mut self, limits: &RateLimits, now: Instant) {
+        self.buckets.retain(|&(class, _), bucket| {
+            limits.limit(class).is_some_and(|limit| {
+                bucket.refill(limit, now);
+                bucket.tokens < f64::from(limit.burst)
+            })
+        });
+        self.prune_at = PRUNE_THRESHOLD.max(self.buckets.len() * 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_buckets() {
+        let limiter = RateLimiter::new(RateLimits {
+            writes: Some(RateLimit { per_second: 10, burst: 2 }),
+            commits: Some(RateLimit { per_second: 1, burst: 1 }),
+            ..RateLimits::default()
+        });
+
+        // Reads are unlimited
+        for _ in 0..100 {
+            assert!(limiter.check(RequestClass::Read, "alice").is_ok());
+        }
+
+        // The burst is available straight away, then requests wait for the refill
+        assert!(limiter.check(RequestClass::Write, "alice").is_ok());
+        assert!(limiter.check(RequestClass::Write, "alice").is_ok());
+        let retry_after = limiter.check(RequestClass::Write, "alice").unwrap_err();
+        assert!(retry_after <= Duration::from_millis(100));
+
+        // Each class and each caller has its own bucket
+        assert!(limiter.check(RequestClass::Commit, "alice").is_ok());
+        assert!(limiter.check(RequestClass::Commit, "alice").is_err());
+        assert!(limiter.check(RequestClass::Write, "bob").is_ok());
+
+        std::thread::sleep(retry_after + Duration::from_millis(10));
+        assert!(limiter.check(RequestClass::Write, "alice").is_ok());
+
+        assert!(RateLimits { reads: Some(RateLimit { per_second: 0, burst: 1 }), ..RateLimits::default() }
+            .validate()
+            .is_err());
+    }
+}
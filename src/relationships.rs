@@ -0,0 +1,77 @@
+//! Directed relationships between users, such as blocks and mutes, that start and end over time
+//! and can be queried at any version.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::FollowInterval;
+
+/// Every pair of users a relationship has held between, with the versions it was in force
+#[derive(Debug, Default)]
+pub struct Relationships {
+    /// Map of (`user_id`, `other_id`) to the versions the relationship was in force, like `follow_intervals`
+    intervals: HashMap<(u64, u64), Vec<FollowInterval>>,
+
+    /// Map of `user_id` to everyone they have ever had the relationship with
+    history: HashMap<u64, HashSet<u64>>,
+}
+
+impl Relationships {
+    /// Start the relationship at `version`. Returns false if it is already in force.
+    pub fn start(&mut self, user_id: u64, other_id: u64, version: u64) -> bool {
+        if self.is_open(user_id, other_id) {
+            return false;
+        }
+        let intervals = self.intervals.entry((user_id, other_id)).or_default();
+        match intervals.last_mut() {
+            // Ended and started again in the same version
+            Some(last) if last.follow_end == version => last.follow_end = u64::MAX,
+            _ => intervals.push(FollowInterval::new(version)),
+        }
+        self.history.entry(user_id).or_default().insert(other_id);
+        true
+    }
+
+    /// End the relationship at `version`. Returns false if it was not in force.
+    pub fn end(&mut self, user_id: u64, other_id: u64, version: u64) -> bool {
+        let Some(last) = self
+            .intervals
+            .get_mut(&(user_id, other_id))
+            .and_then(|intervals| intervals.last_mut())
+            .filter(|last| last.follow_end == u64::MAX)
+        else {
+            return false;
+        };
+        last.follow_end = version;
+        true
+    }
+
+    /// Whether the relationship was in force at `version`. Like follows, it still counts as in
+    /// force at the version it ended.
+    pub fn is_active(&self, user_id: u64, other_id: u64, version: u64) -> bool {
+        self.intervals
+            .get(&(user_id, other_id))
+            .is_some_and(|intervals| intervals.iter().any(|interval| interval.is_active(version)))
+    }
+
+    /// Whether the relationship has not been ended. Unlike [`Self::is_active`] at the current
+    /// version, this is false straight after it ends.
+    pub fn is_open(&self, user_id: u64, other_id: u64) -> bool {
+        self.intervals
+            .get(&(user_id, other_id))
+            .and_then(|intervals| intervals.last())
+            .is_some_and(|last| last.follow_end == u64::MAX)
+    }
+
+    /// Users `user_id` had the relationship with at `version`, sorted by user id
+    pub fn others_at(&self, user_id: u64, version: u64) -> Vec<u64> {
+        let mut others: Vec<u64> = self.history.get(&user_id).map_or_else(Vec::new, |others| {
+            others
+                .iter()
+                .copied()
+                .filter(|&other_id| self.is_active(user_id, other_id, version))
+                .collect()
+        });
+        others.sort_unstable();
+        others
+    }
+}
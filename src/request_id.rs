@@ -0,0 +1,110 @@
+//! Request ids: every gRPC request carries an `x-request-id` metadata entry, taken from the
+//! client or generated, which is echoed in the response and attached to the request's span.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use http::HeaderValue;
+use tracing::Instrument;
+
+use crate::metrics::grpc_code;
+
+/// Metadata key carrying the request id
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Tower layer propagating request ids and wrapping every request in a `grpc_request` span,
+/// so handler and library spans are nested under it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestIdLayer;
+
+impl<S> tower::Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+/// Service wrapper created by [`RequestIdLayer`]
+#[derive(Debug, Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> tower::Service<http::Request<ReqBody>> for RequestIdService<S>
+where
+    S: tower::Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .filter(|id| !id.is_empty())
+            .cloned()
+            .unwrap_or_else(generate_request_id);
+        request.headers_mut().insert(REQUEST_ID_HEADER, request_id.clone());
+
+        let span = tracing::info_span!(
+            "grpc_request",
+            request_id = request_id.to_str().unwrap_or("<invalid>"),
+            path = request.uri().path(),
+        );
+        let started = Instant::now();
+        let response = span.in_scope(|| self.inner.call(request));
+
+        Box::pin(
+            async move {
+                let mut result = response.await;
+                tracing::debug!(
+                    code = ?grpc_code(&result),
+                    elapsed_ms = started.elapsed().as_secs_f64() * 1e3,
+                    "request completed"
+                );
+                if let Ok(response) = &mut result {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+                }
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
+
+fn generate_request_id() -> HeaderValue {
+    HeaderValue::from_str(&format!("{:016x}", rand::random::<u64>())).unwrap_or_else(|_| HeaderValue::from_static("0"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::{Layer, Service};
+
+    #[tokio::test]
+    async fn test_request_id_propagation() {
+        let echo = tower::service_fn(|request: http::Request<()>| async move {
+            // The handler sees the request id in its metadata
+            assert!(request.headers().contains_key(REQUEST_ID_HEADER));
+            Ok::<_, Infallible>(http::Response::new(()))
+        });
+        let mut service = RequestIdLayer.layer(echo);
+
+        let request = http::Request::builder().header(REQUEST_ID_HEADER, "abc123").body(()).unwrap();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc123");
+
+        let response = service.call(http::Request::new(())).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER].len(), 16);
+    }
+}
@@ -1,140 +1,1601 @@
-use std::sync::Mutex;
+// Every handler and helper here returns `tonic::Status` as its error type.
+#![allow(clippy::result_large_err)]
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
-use crate::SocialNetwork;
+use tracing::{instrument, Instrument, Span};
+use crate::auth::{Caller, RolePolicy};
+use crate::config::{AutocommitPolicy, Limits, PersistenceMode, ServerConfig};
+use crate::idempotency::IdempotencyCache;
+use crate::metrics::{LockMode, Metrics};
+use crate::persistence::ChangeLogFile;
+use crate::rate_limit::{RateLimiter, RequestClass};
+use crate::{ChangeKind, FollowOrder, FollowQuota, PageCursor, SocialNetwork};
 
 // Include the generated proto code
 pub mod social_network {
     tonic::include_proto!("social_network");
+
+    /// Encoded descriptors of the service, for gRPC server reflection
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("social_network_descriptor");
 }
 
 use social_network::social_network_service_server::{SocialNetworkService, SocialNetworkServiceServer};
 use social_network::*;
 
+/// Number of chunks a streaming listing buffers ahead of a slow client
+const STREAM_BUFFER: usize = 4;
+
+/// Maximum number of change log entries a change feed copies per lock acquisition
+const WATCH_BATCH_SIZE: usize = 1_000;
+
+/// Number of user events a subscriber can fall behind by before it is disconnected
+const USER_EVENT_BUFFER: usize = 1_000;
+
+/// Senders of the open `SubscribeUserEvents` streams, by the user whose events they receive
+type UserEventSubscribers = HashMap<u64, Vec<mpsc::Sender<Result<UserEvent, Status>>>>;
+
 pub struct SocialNetworkServer {
-    network: Mutex<SocialNetwork>,
+    /// Read handlers, including historical queries, share the lock; only mutations take it exclusively
+    network: Arc<RwLock<SocialNetwork>>,
+
+    /// Length of the change log, published after every mutation to wake up change feeds
+    changes: watch::Sender<usize>,
+
+    /// Set when the server starts shutting down, to close change feeds that would otherwise only
+    /// end when their client disconnects
+    shutting_down: watch::Sender<bool>,
+
+    /// Open user event streams, sent each user's events when a version is committed. Only
+    /// updated with the network lock held, so no commit slips in while a stream subscribes.
+    user_events: Mutex<UserEventSubscribers>,
+
+    /// Responses to recent mutations by idempotency key. Only accessed with the network write lock held.
+    idempotency: Mutex<IdempotencyCache<IdempotencyKey, CachedEntry>>,
+
+    /// Durable copy of the change log when persistence is enabled. Only accessed with the network write lock held.
+    change_log: Mutex<Option<ChangeLogFile>>,
+
+    autocommit: AutocommitPolicy,
+    limits: Limits,
+    metrics: Arc<Metrics>,
+
+    /// Role each RPC requires from authenticated callers
+    roles: RolePolicy,
+
+    rate_limiter: RateLimiter,
+}
+
+/// A mutation response remembered for an idempotency key
+#[derive(Debug, Clone)]
+enum CachedResponse {
+    Follow(FollowResponse),
+    Unfollow(UnfollowResponse),
+    Commit(CommitResponse),
+}
+
+/// An idempotency key, scoped to the caller that sent it so callers never see each other's responses
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct IdempotencyKey {
+    caller: String,
+    key: String,
+}
+
+/// A remembered response and a hash of the request it answered, to catch keys reused for a
+/// different request
+#[derive(Debug, Clone)]
+struct CachedEntry {
+    request_hash: u64,
+    response: CachedResponse,
 }
 
 impl SocialNetworkServer {
     pub fn new() -> Self {
+        Self::with_roles(&ServerConfig::default(), RolePolicy::default())
+    }
+
+    /// Create a server with an empty network, applying the config's limits, autocommit policy
+    /// and role overrides. Persisted state is only loaded by [`Self::restore`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a role override names a method the service does not have.
+    pub fn with_config(config: &ServerConfig) -> Result<Self, String> {
+        let roles = match &config.auth {
+            Some(auth) => RolePolicy::new(&auth.roles)?,
+            None => RolePolicy::default(),
+        };
+        Ok(Self::with_roles(config, roles))
+    }
+
+    fn with_roles(config: &ServerConfig, roles: RolePolicy) -> Self {
+        let mut network = SocialNetwork::new();
+        apply_follow_rules(config, &mut network);
         Self {
-            network: Mutex::new(SocialNetwork::new()),
+            network: Arc::new(RwLock::new(network)),
+            changes: watch::channel(0).0,
+            shutting_down: watch::channel(false).0,
+            user_events: Mutex::new(HashMap::new()),
+            idempotency: Mutex::new(IdempotencyCache::new(config.limits.idempotency_keys)),
+            change_log: Mutex::new(None),
+            autocommit: config.autocommit,
+            limits: config.limits,
+            metrics: Arc::new(Metrics::new()),
+            roles,
+            rate_limiter: RateLimiter::new(config.rate_limits),
         }
     }
+
+    /// Metrics of this server. Per-RPC metrics are only recorded when the gRPC server is built
+    /// with a [`crate::metrics::MetricsLayer`] sharing them.
+    #[must_use]
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Encode the metrics in the Prometheus text format, refreshing the graph size gauges
+    #[must_use]
+    pub fn encode_metrics(&self) -> String {
+        self.metrics.observe_graph(&self.read_network());
+        self.metrics.encode()
+    }
+
+    /// Take the network lock shared, recording the wait
+    fn read_network(&self) -> RwLockReadGuard<'_, SocialNetwork> {
+        let started = Instant::now();
+        let network = self.network.read().unwrap();
+        self.metrics.observe_lock_wait(LockMode::Read, started.elapsed());
+        network
+    }
+
+    /// Take the network lock exclusively, recording the wait
+    fn write_network(&self) -> RwLockWriteGuard<'_, SocialNetwork> {
+        let started = Instant::now();
+        let network = self.network.write().unwrap();
+        self.metrics.observe_lock_wait(LockMode::Write, started.elapsed());
+        network
+    }
+
+    /// Create a server from a config and restore its persisted state
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a role override is invalid, or if the change log cannot be opened
+    /// or replayed.
+    pub fn from_config(config: &ServerConfig) -> Result<Self, String> {
+        let server = Self::with_config(config)?;
+        server.restore(config)?;
+        Ok(server)
+    }
+
+    /// Replay the change log in the data directory when persistence is enabled, replacing the
+    /// network. Must run before the server takes requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the change log cannot be opened or replayed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock is poisoned.
+    pub fn restore(&self, config: &ServerConfig) -> Result<(), String> {
+        if config.persistence == PersistenceMode::Memory {
+            return Ok(());
+        }
+
+        // The replayed follows were accepted before, so the follow rules only apply from here on
+        let (change_log, mut restored) = ChangeLogFile::open(&config.data_dir)?;
+        apply_follow_rules(config, &mut restored);
+        let mut network = self.write_network();
+        *network = restored;
+        *self.change_log.lock().unwrap() = Some(change_log);
+        self.changes.send_replace(network.changes().len());
+        drop(network);
+        Ok(())
+    }
+
+    /// Commit any uncommitted changes every `interval`, for the interval autocommit policy.
+    /// Runs until the task is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the network lock is poisoned.
+    pub async fn autocommit_every(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let mut network = self.write_network();
+            if network.has_uncommitted_changes() {
+                network.commit();
+                if let Err(status) = self.publish_changes(&mut network) {
+                    tracing::error!(error = status.message(), "Autocommit failed");
+                }
+            }
+            drop(network);
+        }
+    }
+
+    /// Check that the caller may call `method` and is within its rate limit. Requests that are
+    /// not authorized do not count against the rate limit.
+    fn admit<T>(&self, request: &Request<T>, method: &str) -> Result<(), Status> {
+        self.authorize(request, method)?;
+        self.check_rate_limit(request, method)
+    }
+
+    /// Fail with `RESOURCE_EXHAUSTED` if the caller, or the peer address when there is no
+    /// authenticated caller, has used up the rate limit for `method`'s class of request. The
+    /// status carries the time to wait before retrying.
+    fn check_rate_limit<T>(&self, request: &Request<T>, method: &str) -> Result<(), Status> {
+        let class = RequestClass::of(method);
+        self.rate_limiter.check(class, &caller_key(request)).map_err(|retry_after| {
+            let retry_after_ms = u64::try_from(retry_after.as_millis()).unwrap_or(u64::MAX).max(1);
+            let mut status = Status::resource_exhausted(format!(
+                "Rate limit for {class} requests exceeded, retry in {retry_after_ms} ms"
+            ));
+            status.metadata_mut().insert("grpc-retry-pushback-ms", retry_after_ms.into());
+            status.metadata_mut().insert("retry-after", retry_after.as_secs().max(1).into());
+            status
+        })
+    }
+
+    /// Fail with `PERMISSION_DENIED` unless the caller holds the role `method` requires. Without
+    /// authentication there is no caller, and every RPC is allowed.
+    fn authorize<T>(&self, request: &Request<T>, method: &str) -> Result<(), Status> {
+        let Some(caller) = request.extensions().get::<Caller>() else {
+            return Ok(());
+        };
+        let required = self.roles.required_role(method);
+        if caller.role < required {
+            return Err(Status::permission_denied(format!(
+                "{method} requires the {required:?} role but {} is a {:?}",
+                caller.name, caller.role
+            )));
+        }
+        Ok(())
+    }
+
+    /// Look up the response to an earlier request carrying the same idempotency key from the same
+    /// caller. Fails with `INVALID_ARGUMENT` if the key was used for a different request.
+    fn cached_response(&self, key: Option<&(IdempotencyKey, u64)>) -> Result<Option<CachedResponse>, Status> {
+        let Some((key, request_hash)) = key else {
+            return Ok(None);
+        };
+        let entry = self.idempotency.lock().unwrap().get(key);
+        match entry {
+            Some(entry) if entry.request_hash != *request_hash => Err(idempotency_key_reused()),
+            entry => Ok(entry.map(|entry| entry.response)),
+        }
+    }
+
+    /// Remember the response to a request carrying an idempotency key
+    fn cache_response(&self, key: Option<(IdempotencyKey, u64)>, response: CachedResponse) {
+        if let Some((key, request_hash)) = key {
+            self.idempotency.lock().unwrap().insert(key, CachedEntry { request_hash, response });
+        }
+    }
+
+    /// Persist any unwritten changes and sync the change log to disk, for a clean shutdown.
+    /// Takes the write lock, so it waits for in-flight mutations to finish.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the change log cannot be written or synced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock is poisoned.
+    pub fn flush(&self) -> Result<(), String> {
+        let network = self.write_network();
+        if let Some(change_log) = self.change_log.lock().unwrap().as_mut() {
+            change_log.append(&network)?;
+            change_log.sync()?;
+        }
+        drop(network);
+        Ok(())
+    }
+
+    /// Apply the autocommit policy, persist new changes and wake up change feed subscribers
+    /// after a mutation
+    fn publish_changes(&self, network: &mut SocialNetwork) -> Result<(), Status> {
+        if self.autocommit == AutocommitPolicy::EveryMutation && network.has_uncommitted_changes() {
+            network.commit();
+        }
+        if let Some(change_log) = self.change_log.lock().unwrap().as_mut() {
+            change_log.append(network).map_err(Status::internal)?;
+        }
+        let published = *self.changes.borrow();
+        self.send_user_events(network, published);
+        self.changes.send_replace(network.changes().len());
+        Ok(())
+    }
+
+    /// Send the follows and unfollows of every version committed since the change log held
+    /// `published` entries to the subscribers of their followees
+    fn send_user_events(&self, network: &SocialNetwork, published: usize) {
+        let mut subscribers = self.user_events.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let commits = network.changes()[published..].iter().filter(|change| change.kind == ChangeKind::Commit);
+        for commit in commits {
+            let mut events: HashMap<u64, Vec<UserEvent>> = HashMap::new();
+            for change in network.changes_since(commit.version).iter().take_while(|change| change.version == commit.version) {
+                if subscribers.contains_key(&change.followee_id) {
+                    events.entry(change.followee_id).or_default().extend(user_event(change));
+                }
+            }
+            for (user_id, events) in events {
+                if let Some(senders) = subscribers.get_mut(&user_id) {
+                    senders.retain(|tx| send_user_events(tx, &events));
+                    if senders.is_empty() {
+                        subscribers.remove(&user_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// End every open change feed and user event stream with `UNAVAILABLE`, so a graceful
+    /// shutdown does not wait for their clients to disconnect. Feeds opened afterwards end
+    /// straight away.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the user event subscribers lock is poisoned.
+    pub fn close_change_feeds(&self) {
+        self.shutting_down.send_replace(true);
+        for tx in self.user_events.lock().unwrap().drain().flat_map(|(_, senders)| senders) {
+            let _ = tx.try_send(Err(Status::unavailable("Server is shutting down")));
+        }
+    }
+
+    /// Tail the change log from `from_version` onward in a background task, streaming whatever
+    /// `handle` emits for each change. Runs until the client goes away or the server shuts down.
+    fn follow_change_log<T, F>(&self, from_version: Option<u64>, mut handle: F) -> ReceiverStream<Result<T, Status>>
+    where
+        T: Send + 'static,
+        F: FnMut(&crate::ChangeEvent) -> Vec<T> + Send + 'static,
+    {
+        let network = Arc::clone(&self.network);
+
+        // Subscribe before reading the log so no change can slip in between
+        let mut changed = self.changes.subscribe();
+        let mut shutting_down = self.shutting_down.subscribe();
+        let mut next = {
+            let network = network.read().unwrap();
+            let from_version = from_version.unwrap_or_else(|| network.current_version());
+            network.changes().len() - network.changes_since(from_version).len()
+        };
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            let feed = async {
+                loop {
+                    let batch: Vec<crate::ChangeEvent> = network.read().unwrap().changes()[next..]
+                        .iter()
+                        .take(WATCH_BATCH_SIZE)
+                        .copied()
+                        .collect();
+                    next += batch.len();
+
+                    if batch.is_empty() {
+                        tokio::select! {
+                            result = changed.changed() => if result.is_err() { break },
+                            () = tx.closed() => break,
+                        }
+                    }
+                    for item in batch.iter().flat_map(&mut handle) {
+                        if tx.send(Ok(item)).await.is_err() {
+                            // The client went away
+                            return;
+                        }
+                    }
+                }
+            };
+            tokio::select! {
+                () = feed => {}
+                _ = shutting_down.wait_for(|&shutting_down| shutting_down) => {
+                    let _ = tx.try_send(Err(Status::unavailable("Server is shutting down")));
+                }
+            }
+        }.in_current_span());
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Stream `user_ids` in ascending chunks of `chunk_size` from a background task.
+    ///
+    /// The ids are snapshotted by the caller, so the network lock is not held while the
+    /// client drains the stream.
+    fn stream_ids(version: u64, mut user_ids: Vec<u64>, chunk_size: usize) -> ReceiverStream<Result<FollowIdsChunk, Status>> {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            user_ids.sort_unstable();
+            for chunk in user_ids.chunks(chunk_size) {
+                let chunk = FollowIdsChunk { version, user_ids: chunk.to_vec() };
+                if tx.send(Ok(chunk)).await.is_err() {
+                    // The client went away
+                    break;
+                }
+            }
+        }.in_current_span());
+        ReceiverStream::new(rx)
+    }
 }
 
 #[tonic::async_trait]
 impl SocialNetworkService for SocialNetworkServer {
+    #[instrument(skip_all, fields(follower_id = request.get_ref().follower_id, followee_id = request.get_ref().followee_id, expected_version = ?request.get_ref().expected_version, version))]
     async fn follow(
         &self,
         request: Request<FollowRequest>,
     ) -> Result<Response<FollowResponse>, Status> {
+        self.admit(&request, "Follow")?;
+        check_caller_acts_as(&request, request.get_ref().follower_id)?;
+        let idempotency_key = idempotency_key(&request, request.get_ref().idempotency_key.as_ref());
         let req = request.into_inner();
-        let mut network = self.network.lock().unwrap();
+        let mut network = self.write_network();
+        match self.cached_response(idempotency_key.as_ref())? {
+            Some(CachedResponse::Follow(response)) => return Ok(Response::new(response)),
+            Some(_) => return Err(idempotency_key_reused()),
+            None => {}
+        }
+        check_expected_version(&network, req.expected_version)?;
         
-        match network.follow(req.follower_id, req.followee_id) {
+        let response = match network.follow(req.follower_id, req.followee_id) {
             Ok(was_new_follow) => {
-                Ok(Response::new(FollowResponse {
+                self.publish_changes(&mut network)?;
+                FollowResponse {
                     success: true,
                     error_message: String::new(),
                     was_new_follow,
-                }))
+                }
             }
-            Err(error_msg) => {
-                Ok(Response::new(FollowResponse {
+            Err(error) => {
+                FollowResponse {
                     success: false,
-                    error_message: error_msg,
+                    error_message: error.to_string(),
                     was_new_follow: false,
-                }))
+                }
             }
-        }
+        };
+        self.cache_response(idempotency_key, CachedResponse::Follow(response.clone()));
+        Span::current().record("version", network.current_version());
+        drop(network);
+        
+        Ok(Response::new(response))
     }
 
+    #[instrument(skip_all, fields(follower_id = request.get_ref().follower_id, followee_id = request.get_ref().followee_id, expected_version = ?request.get_ref().expected_version, version))]
     async fn unfollow(
         &self,
         request: Request<UnfollowRequest>,
     ) -> Result<Response<UnfollowResponse>, Status> {
+        self.admit(&request, "Unfollow")?;
+        check_caller_acts_as(&request, request.get_ref().follower_id)?;
+        let idempotency_key = idempotency_key(&request, request.get_ref().idempotency_key.as_ref());
         let req = request.into_inner();
-        let mut network = self.network.lock().unwrap();
+        let mut network = self.write_network();
+        match self.cached_response(idempotency_key.as_ref())? {
+            Some(CachedResponse::Unfollow(response)) => return Ok(Response::new(response)),
+            Some(_) => return Err(idempotency_key_reused()),
+            None => {}
+        }
+        check_expected_version(&network, req.expected_version)?;
         
-        match network.unfollow(req.follower_id, req.followee_id) {
+        let response = match network.unfollow(req.follower_id, req.followee_id) {
             Ok(was_unfollowed) => {
-                Ok(Response::new(UnfollowResponse {
+                self.publish_changes(&mut network)?;
+                UnfollowResponse {
                     success: true,
                     error_message: String::new(),
                     was_unfollowed,
-                }))
+                }
             }
             Err(error_msg) => {
-                Ok(Response::new(UnfollowResponse {
+                UnfollowResponse {
                     success: false,
                     error_message: error_msg,
                     was_unfollowed: false,
-                }))
+                }
             }
-        }
+        };
+        self.cache_response(idempotency_key, CachedResponse::Unfollow(response.clone()));
+        Span::current().record("version", network.current_version());
+        drop(network);
+        
+        Ok(Response::new(response))
     }
 
+    #[instrument(skip_all, fields(follower_id = request.get_ref().follower_id, followee_id = request.get_ref().followee_id, version = ?request.get_ref().version))]
     async fn is_following(
         &self,
         request: Request<IsFollowingRequest>,
     ) -> Result<Response<IsFollowingResponse>, Status> {
+        self.admit(&request, "IsFollowing")?;
         let req = request.into_inner();
-        let network = self.network.lock().unwrap();
+        let network = self.read_network();
         
         let is_following = network.is_following(req.follower_id, req.followee_id, req.version);
         
         Ok(Response::new(IsFollowingResponse { is_following }))
     }
 
+    #[instrument(skip_all, fields(queries = request.get_ref().queries.len()))]
+    async fn batch_is_following(
+        &self,
+        request: Request<BatchIsFollowingRequest>,
+    ) -> Result<Response<BatchIsFollowingResponse>, Status> {
+        self.admit(&request, "BatchIsFollowing")?;
+        let req = request.into_inner();
+        check_batch_size(&self.limits, req.queries.len())?;
+        let network = self.read_network();
+
+        let is_following = req
+            .queries
+            .iter()
+            .map(|query| network.is_following(query.follower_id, query.followee_id, query.version))
+            .collect();
+        drop(network);
+
+        Ok(Response::new(BatchIsFollowingResponse { is_following }))
+    }
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, candidates = request.get_ref().candidate_ids.len(), version = ?request.get_ref().version))]
+    async fn follows_any(
+        &self,
+        request: Request<FollowsAnyRequest>,
+    ) -> Result<Response<FollowsAnyResponse>, Status> {
+        self.admit(&request, "FollowsAny")?;
+        let req = request.into_inner();
+        check_batch_size(&self.limits, req.candidate_ids.len())?;
+        let followee_ids = self.read_network().follows_any(req.user_id, &req.candidate_ids, req.version);
+
+        Ok(Response::new(FollowsAnyResponse { followee_ids }))
+    }
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, candidates = request.get_ref().candidate_ids.len(), version = ?request.get_ref().version))]
+    async fn followed_by_many(
+        &self,
+        request: Request<FollowedByManyRequest>,
+    ) -> Result<Response<FollowedByManyResponse>, Status> {
+        self.admit(&request, "FollowedByMany")?;
+        let req = request.into_inner();
+        check_batch_size(&self.limits, req.candidate_ids.len())?;
+        let follower_ids = self.read_network().followed_by_many(req.user_id, &req.candidate_ids, req.version);
+
+        Ok(Response::new(FollowedByManyResponse { follower_ids }))
+    }
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, version = ?request.get_ref().version, page_size = request.get_ref().page_size))]
     async fn get_followers(
         &self,
         request: Request<GetFollowersRequest>,
     ) -> Result<Response<GetFollowersResponse>, Status> {
+        self.admit(&request, "GetFollowers")?;
         let req = request.into_inner();
-        let network = self.network.lock().unwrap();
+        let order = follow_order(req.order());
+        let (limit, cursor) = page_params(&self.limits, req.page_size, &req.page_token)?;
+        let network = self.read_network();
         
-        let followers = network.get_followers(req.user_id);
+        let page = network.get_followers_page(req.user_id, req.version, order, cursor, limit);
         
         Ok(Response::new(GetFollowersResponse {
-            follower_ids: followers,
+            follower_ids: page.user_ids,
+            next_page_token: page_token(page.next_cursor),
         }))
     }
 
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, version = ?request.get_ref().version, page_size = request.get_ref().page_size))]
     async fn get_followees(
         &self,
         request: Request<GetFolloweesRequest>,
     ) -> Result<Response<GetFolloweesResponse>, Status> {
+        self.admit(&request, "GetFollowees")?;
         let req = request.into_inner();
-        let network = self.network.lock().unwrap();
+        let order = follow_order(req.order());
+        let (limit, cursor) = page_params(&self.limits, req.page_size, &req.page_token)?;
+        let network = self.read_network();
         
-        let followees = network.get_followees(req.user_id);
+        let page = network.get_followees_page(req.user_id, req.version, order, cursor, limit);
         
         Ok(Response::new(GetFolloweesResponse {
-            followee_ids: followees,
+            followee_ids: page.user_ids,
+            next_page_token: page_token(page.next_cursor),
         }))
     }
 
+    type StreamFollowersStream = ReceiverStream<Result<FollowIdsChunk, Status>>;
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, version = ?request.get_ref().version))]
+    async fn stream_followers(
+        &self,
+        request: Request<StreamFollowersRequest>,
+    ) -> Result<Response<Self::StreamFollowersStream>, Status> {
+        self.admit(&request, "StreamFollowers")?;
+        let req = request.into_inner();
+        let (version, followers) = {
+            let network = self.read_network();
+            // Without a version, stream the live followers, like the paged listing
+            let version = req.version.unwrap_or_else(|| network.current_version());
+            (version, network.followers_at(req.user_id, req.version))
+        };
+
+        Ok(Response::new(Self::stream_ids(version, followers, page_size(&self.limits, req.chunk_size))))
+    }
+
+    type StreamFolloweesStream = ReceiverStream<Result<FollowIdsChunk, Status>>;
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, version = ?request.get_ref().version))]
+    async fn stream_followees(
+        &self,
+        request: Request<StreamFolloweesRequest>,
+    ) -> Result<Response<Self::StreamFolloweesStream>, Status> {
+        self.admit(&request, "StreamFollowees")?;
+        let req = request.into_inner();
+        let (version, followees) = {
+            let network = self.read_network();
+            // Without a version, stream the live followees, like the paged listing
+            let version = req.version.unwrap_or_else(|| network.current_version());
+            (version, network.followees_at(req.user_id, req.version))
+        };
+
+        Ok(Response::new(Self::stream_ids(version, followees, page_size(&self.limits, req.chunk_size))))
+    }
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, version = ?request.get_ref().version))]
+    async fn get_follower_count(
+        &self,
+        request: Request<GetFollowerCountRequest>,
+    ) -> Result<Response<GetFollowerCountResponse>, Status> {
+        self.admit(&request, "GetFollowerCount")?;
+        let req = request.into_inner();
+        let count = self.read_network().follower_count(req.user_id, req.version);
+
+        Ok(Response::new(GetFollowerCountResponse { count }))
+    }
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, version = ?request.get_ref().version))]
+    async fn get_followee_count(
+        &self,
+        request: Request<GetFolloweeCountRequest>,
+    ) -> Result<Response<GetFolloweeCountResponse>, Status> {
+        self.admit(&request, "GetFolloweeCount")?;
+        let req = request.into_inner();
+        let count = self.read_network().followee_count(req.user_id, req.version);
+
+        Ok(Response::new(GetFolloweeCountResponse { count }))
+    }
+
+    #[instrument(skip_all, fields(mutations = request.get_ref().mutations.len(), commit = request.get_ref().commit, version))]
+    async fn batch_mutate(
+        &self,
+        request: Request<BatchMutateRequest>,
+    ) -> Result<Response<BatchMutateResponse>, Status> {
+        self.admit(&request, "BatchMutate")?;
+        // Committing must not be a way around the role or the rate limit `Commit` has
+        if request.get_ref().commit {
+            self.admit(&request, "Commit")?;
+        }
+        check_batch_size(&self.limits, request.get_ref().mutations.len())?;
+        for mutation in &request.get_ref().mutations {
+            check_caller_acts_as(&request, mutation.follower_id)?;
+        }
+        let req = request.into_inner();
+
+        let mutations: Vec<crate::Mutation> = req.mutations.iter().map(mutation).collect();
+
+        // Apply (and commit) the whole batch under a single lock acquisition
+        let mut network = self.write_network();
+        let results = network
+            .apply_batch(&mutations)
+            .into_iter()
+            .map(|result| match result {
+                Ok(changed) => MutationResult { success: true, error_message: String::new(), changed },
+                Err(error_message) => MutationResult { success: false, error_message, changed: false },
+            })
+            .collect();
+        if req.commit {
+            network.commit();
+        }
+        self.publish_changes(&mut network)?;
+        let version = network.current_version();
+        drop(network);
+        Span::current().record("version", version);
+
+        Ok(Response::new(BatchMutateResponse { results, version }))
+    }
+
+    #[instrument(skip_all, fields(expected_version = ?request.get_ref().expected_version, version))]
     async fn commit(
         &self,
-        _request: Request<CommitRequest>,
+        request: Request<CommitRequest>,
     ) -> Result<Response<CommitResponse>, Status> {
-        let mut network = self.network.lock().unwrap();
+        self.admit(&request, "Commit")?;
+        let idempotency_key = idempotency_key(&request, request.get_ref().idempotency_key.as_ref());
+        let req = request.into_inner();
+        let mut network = self.write_network();
+        match self.cached_response(idempotency_key.as_ref())? {
+            Some(CachedResponse::Commit(response)) => return Ok(Response::new(response)),
+            Some(_) => return Err(idempotency_key_reused()),
+            None => {}
+        }
+        check_expected_version(&network, req.expected_version)?;
         let version = network.commit();
+        self.publish_changes(&mut network)?;
+        self.cache_response(idempotency_key, CachedResponse::Commit(CommitResponse { version }));
+        Span::current().record("version", version);
         
         Ok(Response::new(CommitResponse { version }))
     }
 
+    #[instrument(skip_all)]
     async fn get_current_version(
         &self,
-        _request: Request<GetCurrentVersionRequest>,
+        request: Request<GetCurrentVersionRequest>,
     ) -> Result<Response<GetCurrentVersionResponse>, Status> {
-        let network = self.network.lock().unwrap();
+        self.admit(&request, "GetCurrentVersion")?;
+        let network = self.read_network();
         let version = network.current_version();
         
         Ok(Response::new(GetCurrentVersionResponse { version }))
     }
+
+    type WatchChangesStream = ReceiverStream<Result<ChangeEvent, Status>>;
+
+    #[instrument(skip_all, fields(from_version = request.get_ref().from_version))]
+    async fn watch_changes(
+        &self,
+        request: Request<WatchChangesRequest>,
+    ) -> Result<Response<Self::WatchChangesStream>, Status> {
+        self.admit(&request, "WatchChanges")?;
+        let req = request.into_inner();
+        let stream = self.follow_change_log(Some(req.from_version), |change| change_event(change).into_iter().collect());
+
+        Ok(Response::new(stream))
+    }
+
+    type SubscribeUserEventsStream = ReceiverStream<Result<UserEvent, Status>>;
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id))]
+    async fn subscribe_user_events(
+        &self,
+        request: Request<SubscribeUserEventsRequest>,
+    ) -> Result<Response<Self::SubscribeUserEventsStream>, Status> {
+        self.admit(&request, "SubscribeUserEvents")?;
+        let user_id = request.into_inner().user_id;
+
+        // Register under the network lock so the stream gets every version committed after it
+        // subscribes, including the changes already pending at the current version
+        let (tx, rx) = mpsc::channel(USER_EVENT_BUFFER);
+        let network = self.read_network();
+        let mut subscribers = self.user_events.lock().unwrap();
+        if *self.shutting_down.borrow() {
+            let _ = tx.try_send(Err(Status::unavailable("Server is shutting down")));
+        } else {
+            let senders = subscribers.entry(user_id).or_default();
+            senders.retain(|tx| !tx.is_closed());
+            senders.push(tx);
+        }
+        drop(subscribers);
+        drop(network);
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[instrument(skip_all, fields(version = ?request.get_ref().version))]
+    async fn get_graph_stats(
+        &self,
+        request: Request<GetGraphStatsRequest>,
+    ) -> Result<Response<GetGraphStatsResponse>, Status> {
+        self.admit(&request, "GetGraphStats")?;
+        let req = request.into_inner();
+        let stats = self.read_network().stats(req.version);
+
+        let histogram = |buckets: BTreeMap<u64, u64>| {
+            buckets
+                .into_iter()
+                .map(|(degree, user_count)| DegreeBucket { degree, user_count })
+                .collect()
+        };
+
+        Ok(Response::new(GetGraphStatsResponse {
+            version: stats.version,
+            user_count: stats.user_count,
+            edge_count: stats.edge_count,
+            in_degree_histogram: histogram(stats.in_degree_histogram),
+            out_degree_histogram: histogram(stats.out_degree_histogram),
+            max_in_degree: stats.max_in_degree,
+            max_in_degree_user_ids: stats.max_in_degree_users,
+            max_out_degree: stats.max_out_degree,
+            max_out_degree_user_ids: stats.max_out_degree_users,
+            reciprocity: stats.reciprocity,
+        }))
+    }
+
+    #[instrument(skip_all, fields(blocker_id = request.get_ref().blocker_id, blocked_id = request.get_ref().blocked_id, expected_version = ?request.get_ref().expected_version, version))]
+    async fn block(
+        &self,
+        request: Request<BlockRequest>,
+    ) -> Result<Response<BlockResponse>, Status> {
+        self.admit(&request, "Block")?;
+        check_caller_acts_as(&request, request.get_ref().blocker_id)?;
+        let req = request.into_inner();
+        let mut network = self.write_network();
+        check_expected_version(&network, req.expected_version)?;
+
+        let response = match network.block(req.blocker_id, req.blocked_id) {
+            Ok(was_new_block) => {
+                self.publish_changes(&mut network)?;
+                BlockResponse {
+                    success: true,
+                    error_message: String::new(),
+                    was_new_block,
+                }
+            }
+            Err(error_message) => BlockResponse {
+                success: false,
+                error_message,
+                was_new_block: false,
+            },
+        };
+        Span::current().record("version", network.current_version());
+        drop(network);
+
+        Ok(Response::new(response))
+    }
+
+    #[instrument(skip_all, fields(blocker_id = request.get_ref().blocker_id, blocked_id = request.get_ref().blocked_id, expected_version = ?request.get_ref().expected_version, version))]
+    async fn unblock(
+        &self,
+        request: Request<UnblockRequest>,
+    ) -> Result<Response<UnblockResponse>, Status> {
+        self.admit(&request, "Unblock")?;
+        check_caller_acts_as(&request, request.get_ref().blocker_id)?;
+        let req = request.into_inner();
+        let mut network = self.write_network();
+        check_expected_version(&network, req.expected_version)?;
+
+        let response = match network.unblock(req.blocker_id, req.blocked_id) {
+            Ok(was_unblocked) => {
+                self.publish_changes(&mut network)?;
+                UnblockResponse {
+                    success: true,
+                    error_message: String::new(),
+                    was_unblocked,
+                }
+            }
+            Err(error_message) => UnblockResponse {
+                success: false,
+                error_message,
+                was_unblocked: false,
+            },
+        };
+        Span::current().record("version", network.current_version());
+        drop(network);
+
+        Ok(Response::new(response))
+    }
+
+    #[instrument(skip_all, fields(blocker_id = request.get_ref().blocker_id, blocked_id = request.get_ref().blocked_id, version = ?request.get_ref().version))]
+    async fn is_blocked(
+        &self,
+        request: Request<IsBlockedRequest>,
+    ) -> Result<Response<IsBlockedResponse>, Status> {
+        self.admit(&request, "IsBlocked")?;
+        check_caller_acts_as(&request, request.get_ref().blocker_id)?;
+        let req = request.into_inner();
+        let is_blocked = self.read_network().is_blocked(req.blocker_id, req.blocked_id, req.version);
+
+        Ok(Response::new(IsBlockedResponse { is_blocked }))
+    }
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, version = ?request.get_ref().version))]
+    async fn get_blocked_users(
+        &self,
+        request: Request<GetBlockedUsersRequest>,
+    ) -> Result<Response<GetBlockedUsersResponse>, Status> {
+        self.admit(&request, "GetBlockedUsers")?;
+        check_caller_acts_as(&request, request.get_ref().user_id)?;
+        let req = request.into_inner();
+        let blocked_ids = self.read_network().blocked_users(req.user_id, req.version);
+
+        Ok(Response::new(GetBlockedUsersResponse { blocked_ids }))
+    }
+
+    #[instrument(skip_all, fields(muter_id = request.get_ref().muter_id, muted_id = request.get_ref().muted_id, expected_version = ?request.get_ref().expected_version, version))]
+    async fn mute(
+        &self,
+        request: Request<MuteRequest>,
+    ) -> Result<Response<MuteResponse>, Status> {
+        self.admit(&request, "Mute")?;
+        check_caller_acts_as(&request, request.get_ref().muter_id)?;
+        let req = request.into_inner();
+        let mut network = self.write_network();
+        check_expected_version(&network, req.expected_version)?;
+
+        let response = match network.mute(req.muter_id, req.muted_id) {
+            Ok(was_new_mute) => {
+                self.publish_changes(&mut network)?;
+                MuteResponse {
+                    success: true,
+                    error_message: String::new(),
+                    was_new_mute,
+                }
+            }
+            Err(error_message) => MuteResponse {
+                success: false,
+                error_message,
+                was_new_mute: false,
+            },
+        };
+        Span::current().record("version", network.current_version());
+        drop(network);
+
+        Ok(Response::new(response))
+    }
+
+    #[instrument(skip_all, fields(muter_id = request.get_ref().muter_id, muted_id = request.get_ref().muted_id, expected_version = ?request.get_ref().expected_version, version))]
+    async fn unmute(
+        &self,
+        request: Request<UnmuteRequest>,
+    ) -> Result<Response<UnmuteResponse>, Status> {
+        self.admit(&request, "Unmute")?;
+        check_caller_acts_as(&request, request.get_ref().muter_id)?;
+        let req = request.into_inner();
+        let mut network = self.write_network();
+        check_expected_version(&network, req.expected_version)?;
+
+        let response = match network.unmute(req.muter_id, req.muted_id) {
+            Ok(was_unmuted) => {
+                self.publish_changes(&mut network)?;
+                UnmuteResponse {
+                    success: true,
+                    error_message: String::new(),
+                    was_unmuted,
+                }
+            }
+            Err(error_message) => UnmuteResponse {
+                success: false,
+                error_message,
+                was_unmuted: false,
+            },
+        };
+        Span::current().record("version", network.current_version());
+        drop(network);
+
+        Ok(Response::new(response))
+    }
+
+    #[instrument(skip_all, fields(muter_id = request.get_ref().muter_id, muted_id = request.get_ref().muted_id, version = ?request.get_ref().version))]
+    async fn is_muted(
+        &self,
+        request: Request<IsMutedRequest>,
+    ) -> Result<Response<IsMutedResponse>, Status> {
+        self.admit(&request, "IsMuted")?;
+        check_caller_acts_as(&request, request.get_ref().muter_id)?;
+        let req = request.into_inner();
+        let is_muted = self.read_network().is_muted(req.muter_id, req.muted_id, req.version);
+
+        Ok(Response::new(IsMutedResponse { is_muted }))
+    }
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, version = ?request.get_ref().version))]
+    async fn get_muted_users(
+        &self,
+        request: Request<GetMutedUsersRequest>,
+    ) -> Result<Response<GetMutedUsersResponse>, Status> {
+        self.admit(&request, "GetMutedUsers")?;
+        check_caller_acts_as(&request, request.get_ref().user_id)?;
+        let req = request.into_inner();
+        let muted_ids = self.read_network().muted_users(req.user_id, req.version);
+
+        Ok(Response::new(GetMutedUsersResponse { muted_ids }))
+    }
+
+    #[instrument(skip_all, fields(user_id = request.get_ref().user_id, version = ?request.get_ref().version))]
+    async fn get_feed_followees(
+        &self,
+        request: Request<GetFeedFolloweesRequest>,
+    ) -> Result<Response<GetFeedFolloweesResponse>, Status> {
+        self.admit(&request, "GetFeedFollowees")?;
+        // Comparing the feed with the followees would reveal the user's mutes
+        check_caller_acts_as(&request, request.get_ref().user_id)?;
+        let req = request.into_inner();
+        let followee_ids = self.read_network().feed_followees(req.user_id, req.version);
+
+        Ok(Response::new(GetFeedFolloweesResponse { followee_ids }))
+    }
+}
+
+/// Enforce the configured follow policies and quota on `network`
+fn apply_follow_rules(config: &ServerConfig, network: &mut SocialNetwork) {
+    config.follow_policy.apply(network);
+    network.set_follow_quota(config.follow_quota.map(FollowQuota::from));
+}
+
+/// Reject a mutation whose `expected_version` is no longer the current version, giving writers
+/// compare-and-swap semantics. Must be called with the write lock held.
+fn check_expected_version(network: &SocialNetwork, expected_version: Option<u64>) -> Result<(), Status> {
+    match expected_version {
+        Some(expected_version) if expected_version != network.current_version() => {
+            Err(Status::failed_precondition(format!(
+                "Expected version {expected_version} but the current version is {}",
+                network.current_version()
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Callers authenticated as a user may only change their own follows, blocks and mutes, and only
+/// see their own mutes; admins, and all callers when authentication is disabled, may act as anyone
+fn check_caller_acts_as<T>(request: &Request<T>, user_id: u64) -> Result<(), Status> {
+    match request.extensions().get::<Caller>() {
+        Some(caller) if !caller.can_act_as(user_id) => Err(Status::permission_denied(format!(
+            "{} may not act as user {user_id}",
+            caller.name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn idempotency_key_reused() -> Status {
+    Status::invalid_argument("Idempotency key was already used for a different request")
+}
+
+/// Identity requests are attributed to: the authenticated caller, or the peer address when
+/// authentication is disabled
+fn caller_key<T>(request: &Request<T>) -> String {
+    match (request.extensions().get::<Caller>(), request.remote_addr()) {
+        (Some(caller), _) => format!("caller:{}", caller.name),
+        (None, Some(addr)) => format!("peer:{}", addr.ip()),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+/// The caller-scoped idempotency key of a request that carries one, with a hash of the request
+fn idempotency_key<T: prost::Message>(request: &Request<T>, key: Option<&String>) -> Option<(IdempotencyKey, u64)> {
+    let key = key.filter(|key| !key.is_empty())?;
+    let mut hasher = DefaultHasher::new();
+    request.get_ref().encode_to_vec().hash(&mut hasher);
+    Some((IdempotencyKey { caller: caller_key(request), key: key.clone() }, hasher.finish()))
+}
+
+/// Reject batch requests with more than `max_batch_size` items
+fn check_batch_size(limits: &Limits, len: usize) -> Result<(), Status> {
+    if len > limits.max_batch_size {
+        return Err(Status::invalid_argument(format!(
+            "Batch of {len} items exceeds the limit of {}",
+            limits.max_batch_size
+        )));
+    }
+    Ok(())
+}
+
+fn mutation(mutation: &Mutation) -> crate::Mutation {
+    let (follower_id, followee_id) = (mutation.follower_id, mutation.followee_id);
+    match mutation.kind() {
+        MutationKind::Follow => crate::Mutation::Follow { follower_id, followee_id },
+        MutationKind::Unfollow => crate::Mutation::Unfollow { follower_id, followee_id },
+    }
+}
+
+/// The public form of a change, or `None` for mutes, which only the muter may see
+fn change_event(change: &crate::ChangeEvent) -> Option<ChangeEvent> {
+    let kind = match change.kind {
+        ChangeKind::Follow => social_network::ChangeKind::Follow,
+        ChangeKind::Unfollow => social_network::ChangeKind::Unfollow,
+        ChangeKind::Commit => social_network::ChangeKind::Commit,
+        ChangeKind::Block | ChangeKind::Unblock | ChangeKind::Mute | ChangeKind::Unmute => return None,
+    };
+    Some(ChangeEvent {
+        sequence: change.sequence,
+        kind: kind.into(),
+        version: change.version,
+        follower_id: change.follower_id,
+        followee_id: change.followee_id,
+    })
+}
+
+/// Send a committed version's events to a user event stream without waiting. A subscriber
+/// without room for them is sent an error instead, which always fits since a slot is kept free
+/// for it.
+///
+/// Returns whether the stream is still open.
+fn send_user_events(tx: &mpsc::Sender<Result<UserEvent, Status>>, events: &[UserEvent]) -> bool {
+    if events.is_empty() {
+        return !tx.is_closed();
+    }
+    if tx.capacity() <= events.len() {
+        let _ = tx.try_send(Err(Status::resource_exhausted("Subscriber fell too far behind")));
+        return false;
+    }
+    events.iter().all(|event| tx.try_send(Ok(event.clone())).is_ok())
+}
+
+/// The "followed you" / "unfollowed you" event for the followee of a change, or `None` for
+/// changes that are not follows or unfollows
+fn user_event(change: &crate::ChangeEvent) -> Option<UserEvent> {
+    let kind = match change.kind {
+        ChangeKind::Follow => UserEventKind::Followed,
+        ChangeKind::Unfollow => UserEventKind::Unfollowed,
+        ChangeKind::Block | ChangeKind::Unblock | ChangeKind::Mute | ChangeKind::Unmute | ChangeKind::Commit => {
+            return None
+        }
+    };
+    Some(UserEvent {
+        kind: kind.into(),
+        follower_id: change.follower_id,
+        version: change.version,
+    })
+}
+
+const fn follow_order(order: social_network::FollowOrder) -> FollowOrder {
+    match order {
+        social_network::FollowOrder::UserId => FollowOrder::UserId,
+        social_network::FollowOrder::FollowStart => FollowOrder::FollowStart,
+    }
+}
+
+/// Resolve the requested page (or chunk) size of a listing request
+fn page_size(limits: &Limits, requested: u32) -> usize {
+    match usize::try_from(requested) {
+        Ok(0) => limits.default_page_size,
+        Ok(requested) => requested.min(limits.max_page_size),
+        Err(_) => limits.max_page_size,
+    }
+}
+
+/// Resolve the page size and decode the page token of a listing request
+fn page_params(limits: &Limits, page_size: u32, page_token: &str) -> Result<(usize, Option<PageCursor>), Status> {
+    let limit = self::page_size(limits, page_size);
+    if page_token.is_empty() {
+        return Ok((limit, None));
+    }
+
+    let cursor = page_token
+        .split_once(':')
+        .and_then(|(sort_key, user_id)| Some(PageCursor {
+            sort_key: sort_key.parse().ok()?,
+            user_id: user_id.parse().ok()?,
+        }))
+        .ok_or_else(|| Status::invalid_argument(format!("Invalid page token: {page_token}")))?;
+    Ok((limit, Some(cursor)))
+}
+
+/// Encode a page cursor as an opaque page token (empty for the last page)
+fn page_token(cursor: Option<PageCursor>) -> String {
+    cursor.map_or_else(String::new, |cursor| format!("{}:{}", cursor.sort_key, cursor.user_id))
 }
 
 pub fn create_server() -> SocialNetworkServiceServer<SocialNetworkServer> {
     SocialNetworkServiceServer::new(SocialNetworkServer::new())
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AuthConfig, Role};
+    use crate::rate_limit::{RateLimit, RateLimits};
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_stream_followers() {
+        let server = SocialNetworkServer::new();
+        for follower_id in (1..=25).rev() {
+            server.network.write().unwrap().follow(follower_id, 100).unwrap();
+        }
+        let version = server.network.write().unwrap().commit();
+
+        let request = Request::new(StreamFollowersRequest { user_id: 100, version: None, chunk_size: 10 });
+        let mut stream = server.stream_followers(request).await.unwrap().into_inner();
+
+        // Later changes do not affect a stream that has already started
+        server.network.write().unwrap().follow(26, 100).unwrap();
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.unwrap();
+            assert_eq!(chunk.version, version);
+            chunks.push(chunk.user_ids);
+        }
+        assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), vec![10, 10, 5]);
+        assert_eq!(chunks.concat(), (1..=25).collect::<Vec<_>>());
+
+        // An uncommitted unfollow is left out, as it is by GetFollowers
+        server.network.write().unwrap().unfollow(1, 100).unwrap();
+        let request = Request::new(StreamFollowersRequest { user_id: 100, version: None, chunk_size: 100 });
+        let streamed = server.stream_followers(request).await.unwrap().into_inner().next().await.unwrap().unwrap();
+        let listed = server.get_followers(Request::new(GetFollowersRequest { user_id: 100, page_size: 100, ..Default::default() })).await.unwrap();
+        assert_eq!(streamed.user_ids, listed.into_inner().follower_ids);
+        assert!(!streamed.user_ids.contains(&1));
+    }
+
+    #[tokio::test]
+    async fn test_watch_changes() {
+        let server = SocialNetworkServer::new();
+        let follow = |follower_id, followee_id| Request::new(FollowRequest { follower_id, followee_id, ..Default::default() });
+
+        server.follow(follow(1, 2)).await.unwrap();
+        server.commit(Request::new(CommitRequest::default())).await.unwrap();
+        server.follow(follow(2, 3)).await.unwrap();
+
+        // Resume from version 1: the version 0 follow and its commit are skipped
+        let request = Request::new(WatchChangesRequest { from_version: 1 });
+        let mut stream = server.watch_changes(request).await.unwrap().into_inner();
+        let event = stream.next().await.unwrap().unwrap();
+        assert_eq!((event.kind(), event.version, event.follower_id, event.followee_id), (social_network::ChangeKind::Follow, 1, 2, 3));
+        assert_eq!(event.sequence, 2);
+
+        // Live changes are delivered as they happen
+        server.commit(Request::new(CommitRequest::default())).await.unwrap();
+        server.follow(follow(3, 1)).await.unwrap();
+        let event = stream.next().await.unwrap().unwrap();
+        assert_eq!((event.kind(), event.version), (social_network::ChangeKind::Commit, 1));
+        let event = stream.next().await.unwrap().unwrap();
+        assert_eq!((event.kind(), event.version, event.follower_id), (social_network::ChangeKind::Follow, 2, 3));
+
+        // Shutting down ends the feed instead of waiting for the client to go away
+        server.close_change_feeds();
+        assert_eq!(stream.next().await.unwrap().unwrap_err().code(), tonic::Code::Unavailable);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_user_events() {
+        let server = SocialNetworkServer::new();
+        let follow = |follower_id, followee_id| Request::new(FollowRequest { follower_id, followee_id, ..Default::default() });
+        let unfollow = |follower_id, followee_id| Request::new(UnfollowRequest { follower_id, followee_id, ..Default::default() });
+
+        // Committed before subscribing: not delivered
+        server.follow(follow(1, 10)).await.unwrap();
+        server.commit(Request::new(CommitRequest::default())).await.unwrap();
+
+        // Pending when subscribing: delivered with the commit
+        server.follow(follow(2, 10)).await.unwrap();
+        let request = Request::new(SubscribeUserEventsRequest { user_id: 10 });
+        let mut stream = server.subscribe_user_events(request).await.unwrap().into_inner();
+
+        server.follow(follow(3, 11)).await.unwrap(); // another user
+        server.unfollow(unfollow(1, 10)).await.unwrap();
+        server.commit(Request::new(CommitRequest::default())).await.unwrap();
+
+        let event = stream.next().await.unwrap().unwrap();
+        assert_eq!((event.kind(), event.follower_id, event.version), (UserEventKind::Followed, 2, 1));
+        let event = stream.next().await.unwrap().unwrap();
+        assert_eq!((event.kind(), event.follower_id, event.version), (UserEventKind::Unfollowed, 1, 1));
+
+        // Nothing is delivered before the next commit
+        server.follow(follow(4, 10)).await.unwrap();
+        let next = tokio::time::timeout(std::time::Duration::from_millis(50), stream.next()).await;
+        assert!(next.is_err());
+        server.commit(Request::new(CommitRequest::default())).await.unwrap();
+        let event = stream.next().await.unwrap().unwrap();
+        assert_eq!((event.kind(), event.follower_id, event.version), (UserEventKind::Followed, 4, 2));
+
+        // Shutting down ends open streams, and streams opened afterwards straight away
+        server.close_change_feeds();
+        assert_eq!(stream.next().await.unwrap().unwrap_err().code(), tonic::Code::Unavailable);
+        assert!(stream.next().await.is_none());
+        let request = Request::new(SubscribeUserEventsRequest { user_id: 10 });
+        let mut stream = server.subscribe_user_events(request).await.unwrap().into_inner();
+        assert_eq!(stream.next().await.unwrap().unwrap_err().code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn test_expected_version() {
+        let server = SocialNetworkServer::new();
+        let follow = |expected_version| {
+            Request::new(FollowRequest { follower_id: 1, followee_id: 2, expected_version, ..Default::default() })
+        };
+        let commit = |expected_version| Request::new(CommitRequest { expected_version, ..Default::default() });
+
+        server.follow(follow(Some(0))).await.unwrap();
+        assert_eq!(server.commit(commit(Some(0))).await.unwrap().into_inner().version, 1);
+
+        // A second writer still expecting version 0 is rejected without side effects
+        let status = server.commit(commit(Some(0))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let request = Request::new(UnfollowRequest {
+            follower_id: 1,
+            followee_id: 2,
+            expected_version: Some(0),
+            ..Default::default()
+        });
+        let status = server.unfollow(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert!(server.network.read().unwrap().is_following(1, 2, None));
+        assert_eq!(server.network.read().unwrap().current_version(), 1);
+
+        // Without a precondition the mutation always applies
+        server.follow(follow(None)).await.unwrap();
+        assert_eq!(server.commit(commit(None)).await.unwrap().into_inner().version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_keys() {
+        let server = SocialNetworkServer::new();
+        let commit = |key: &str| Request::new(CommitRequest { idempotency_key: Some(key.to_string()), ..Default::default() });
+        let follow = |key: &str| {
+            Request::new(FollowRequest { follower_id: 1, followee_id: 2, idempotency_key: Some(key.to_string()), ..Default::default() })
+        };
+
+        assert!(server.follow(follow("f1")).await.unwrap().into_inner().was_new_follow);
+        // The retry gets the original answer, not "already following"
+        assert!(server.follow(follow("f1")).await.unwrap().into_inner().was_new_follow);
+
+        // A retried commit does not bump the version twice
+        assert_eq!(server.commit(commit("c1")).await.unwrap().into_inner().version, 1);
+        assert_eq!(server.commit(commit("c1")).await.unwrap().into_inner().version, 1);
+        assert_eq!(server.network.read().unwrap().current_version(), 1);
+        assert_eq!(server.commit(commit("c2")).await.unwrap().into_inner().version, 2);
+
+        // Keys cannot be shared between kinds of request, or reused for a different request
+        let status = server.commit(commit("f1")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let mut other_follow = follow("f1");
+        other_follow.get_mut().followee_id = 3;
+        assert_eq!(server.follow(other_follow).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        // Each caller has its own keys
+        let mut bob_follow = Request::new(FollowRequest { follower_id: 2, followee_id: 1, idempotency_key: Some("f1".to_string()), ..Default::default() });
+        bob_follow.extensions_mut().insert(Caller { name: "bob".to_string(), user_id: Some(2), role: Role::Writer });
+        assert!(server.follow(bob_follow).await.unwrap().into_inner().was_new_follow);
+        assert!(server.network.read().unwrap().is_following(2, 1, None));
+    }
+
+    #[tokio::test]
+    async fn test_caller_acts_as_itself() {
+        let auth = AuthConfig { roles: [("BatchMutate".to_string(), Role::Writer)].into(), ..AuthConfig::default() };
+        let server = SocialNetworkServer::with_config(&ServerConfig { auth: Some(auth), ..ServerConfig::default() }).unwrap();
+        let as_caller = |follower_id: u64, caller: Caller| {
+            let mut request = Request::new(FollowRequest { follower_id, followee_id: 9, ..Default::default() });
+            request.extensions_mut().insert(caller);
+            request
+        };
+        let alice = Caller { name: "alice".to_string(), user_id: Some(1), role: Role::Writer };
+        let ops = Caller { name: "ops".to_string(), user_id: None, role: Role::Admin };
+
+        assert!(server.follow(as_caller(1, alice.clone())).await.unwrap().into_inner().success);
+        let status = server.follow(as_caller(2, alice.clone())).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(server.follow(as_caller(2, ops)).await.unwrap().into_inner().success);
+
+        // One mutation on behalf of someone else rejects the whole batch
+        let mutations = [1, 3]
+            .map(|follower_id| Mutation { kind: MutationKind::Unfollow.into(), follower_id, followee_id: 9 })
+            .to_vec();
+        let mut request = Request::new(BatchMutateRequest { mutations, commit: false });
+        request.extensions_mut().insert(alice);
+        assert_eq!(server.batch_mutate(request).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert_eq!(server.network.read().unwrap().get_followers(9), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_roles() {
+        let server = SocialNetworkServer::new();
+        let caller = |role: Role| Caller { name: format!("{role:?}"), user_id: Some(1), role };
+        let commit = |role: Role| {
+            let mut request = Request::new(CommitRequest::default());
+            request.extensions_mut().insert(caller(role));
+            request
+        };
+        let follow = |role: Role| {
+            let mut request = Request::new(FollowRequest { follower_id: 1, followee_id: 2, ..Default::default() });
+            request.extensions_mut().insert(caller(role));
+            request
+        };
+
+        assert_eq!(server.follow(follow(Role::Reader)).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert!(server.follow(follow(Role::Writer)).await.unwrap().into_inner().success);
+        assert_eq!(server.commit(commit(Role::Writer)).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert_eq!(server.commit(commit(Role::Admin)).await.unwrap().into_inner().version, 1);
+
+        let mut request = Request::new(GetFollowersRequest { user_id: 2, ..Default::default() });
+        request.extensions_mut().insert(caller(Role::Reader));
+        assert_eq!(server.get_followers(request).await.unwrap().into_inner().follower_ids, vec![1]);
+
+        // Bulk mutations are for admins, and committing one needs the role `Commit` requires
+        let batch = |role: Role, commit: bool| {
+            let mutations = vec![Mutation { kind: MutationKind::Follow.into(), follower_id: 1, followee_id: 3 }];
+            let mut request = Request::new(BatchMutateRequest { mutations, commit });
+            request.extensions_mut().insert(caller(role));
+            request
+        };
+        assert_eq!(server.batch_mutate(batch(Role::Writer, false)).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert_eq!(server.batch_mutate(batch(Role::Writer, true)).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert_eq!(server.batch_mutate(batch(Role::Admin, true)).await.unwrap().into_inner().version, 2);
+
+        // Roles can be changed per method in the config
+        let roles = [("Commit".to_string(), Role::Writer), ("BatchMutate".to_string(), Role::Reader)];
+        let auth = AuthConfig { roles: roles.into(), ..AuthConfig::default() };
+        let config = ServerConfig { auth: Some(auth), ..ServerConfig::default() };
+        let server = SocialNetworkServer::with_config(&config).unwrap();
+        assert_eq!(server.commit(commit(Role::Writer)).await.unwrap().into_inner().version, 1);
+        assert_eq!(server.batch_mutate(batch(Role::Reader, true)).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert_eq!(server.batch_mutate(batch(Role::Writer, true)).await.unwrap().into_inner().version, 2);
+
+        // An override for a method the service does not have is an error, not ignored
+        let auth = AuthConfig { roles: [("Comit".to_string(), Role::Writer)].into(), ..AuthConfig::default() };
+        let config = ServerConfig { auth: Some(auth), ..ServerConfig::default() };
+        assert!(SocialNetworkServer::with_config(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits() {
+        let config = ServerConfig {
+            rate_limits: RateLimits { commits: Some(RateLimit { per_second: 1, burst: 2 }), ..RateLimits::default() },
+            ..ServerConfig::default()
+        };
+        let server = SocialNetworkServer::with_config(&config).unwrap();
+        let commit = |name: &str| {
+            let mut request = Request::new(CommitRequest::default());
+            request.extensions_mut().insert(Caller { name: name.to_string(), user_id: None, role: Role::Admin });
+            request
+        };
+
+        assert!(server.commit(commit("ops")).await.is_ok());
+        assert!(server.commit(commit("ops")).await.is_ok());
+        let status = server.commit(commit("ops")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        let pushback_ms: u64 = status.metadata().get("grpc-retry-pushback-ms").unwrap().to_str().unwrap().parse().unwrap();
+        assert!((1..=1000).contains(&pushback_ms));
+
+        // Other callers and other kinds of request have their own budget
+        assert!(server.commit(commit("backup")).await.is_ok());
+        assert!(server.get_current_version(Request::new(GetCurrentVersionRequest {})).await.is_ok());
+
+        // A batch that commits draws on the commit budget too
+        let batch = |name: &str| {
+            let mutations = vec![Mutation { kind: MutationKind::Follow.into(), follower_id: 1, followee_id: 2 }];
+            let mut request = Request::new(BatchMutateRequest { mutations, commit: true });
+            request.extensions_mut().insert(Caller { name: name.to_string(), user_id: None, role: Role::Admin });
+            request
+        };
+        assert!(server.batch_mutate(batch("import")).await.is_ok());
+        assert!(server.batch_mutate(batch("import")).await.is_ok());
+        assert_eq!(server.batch_mutate(batch("import")).await.unwrap_err().code(), tonic::Code::ResourceExhausted);
+        assert_eq!(server.commit(commit("import")).await.unwrap_err().code(), tonic::Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn test_autocommit_and_persistence() {
+        let data_dir = std::env::temp_dir().join(format!("socialnetwork-server-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let config = ServerConfig {
+            data_dir: data_dir.clone(),
+            persistence: PersistenceMode::Log,
+            autocommit: AutocommitPolicy::EveryMutation,
+            ..ServerConfig::default()
+        };
+        let follow = |follower_id, followee_id| Request::new(FollowRequest { follower_id, followee_id, ..Default::default() });
+
+        let server = SocialNetworkServer::from_config(&config).unwrap();
+        server.follow(follow(1, 2)).await.unwrap();
+        server.follow(follow(1, 3)).await.unwrap();
+        assert_eq!(server.network.read().unwrap().current_version(), 2);
+        server.flush().unwrap();
+        drop(server);
+
+        // Restarting replays the change log
+        let server = SocialNetworkServer::from_config(&config).unwrap();
+        let network = server.network.read().unwrap();
+        assert_eq!(network.current_version(), 2);
+        assert!(network.is_following(1, 2, Some(0)));
+        assert!(!network.is_following(1, 3, Some(0)));
+        assert!(network.is_following(1, 3, Some(1)));
+        drop(network);
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_block_and_mute_persistence() {
+        let data_dir = std::env::temp_dir().join(format!("socialnetwork-server-block-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        let config = ServerConfig {
+            data_dir: data_dir.clone(),
+            persistence: PersistenceMode::Log,
+            autocommit: AutocommitPolicy::EveryMutation,
+            ..ServerConfig::default()
+        };
+        let follow = |follower_id, followee_id| Request::new(FollowRequest { follower_id, followee_id, ..Default::default() });
+        let block = Request::new(BlockRequest { blocker_id: 2, blocked_id: 1, ..Default::default() });
+
+        let server = SocialNetworkServer::from_config(&config).unwrap();
+        server.follow(follow(1, 2)).await.unwrap();
+        assert!(server.block(block).await.unwrap().into_inner().was_new_block);
+        let response = server.follow(follow(1, 2)).await.unwrap().into_inner();
+        assert!(!response.success && response.error_message.contains("blocked"));
+        server.follow(follow(1, 3)).await.unwrap();
+        server.follow(follow(1, 4)).await.unwrap();
+        let mute = Request::new(MuteRequest { muter_id: 1, muted_id: 3, ..Default::default() });
+        assert!(server.mute(mute).await.unwrap().into_inner().was_new_mute);
+        server.flush().unwrap();
+        drop(server);
+
+        // Replaying the change log restores the block, the follows it removed and the mute
+        let server = SocialNetworkServer::from_config(&config).unwrap();
+        let is_blocked = |version| Request::new(IsBlockedRequest { blocker_id: 2, blocked_id: 1, version });
+        assert!(!server.is_blocked(is_blocked(Some(0))).await.unwrap().into_inner().is_blocked);
+        assert!(server.is_blocked(is_blocked(None)).await.unwrap().into_inner().is_blocked);
+        assert!(!server.network.read().unwrap().is_following(1, 2, None));
+        let blocked = server.get_blocked_users(Request::new(GetBlockedUsersRequest { user_id: 2, version: None })).await.unwrap();
+        assert_eq!(blocked.into_inner().blocked_ids, vec![1]);
+
+        // The mute hides the followee from the feed but keeps the follow
+        let feed = server.get_feed_followees(Request::new(GetFeedFolloweesRequest { user_id: 1, version: None })).await.unwrap();
+        assert_eq!(feed.into_inner().followee_ids, vec![4]);
+        assert!(server.network.read().unwrap().is_following(1, 3, None));
+        drop(server);
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mutes_are_private() {
+        let server = SocialNetworkServer::new();
+        let as_caller = |user_id: u64, mut request: Request<GetMutedUsersRequest>| {
+            request.extensions_mut().insert(Caller { name: format!("user-{user_id}"), user_id: Some(user_id), role: Role::Reader });
+            request
+        };
+        server.follow(Request::new(FollowRequest { follower_id: 1, followee_id: 2, ..Default::default() })).await.unwrap();
+        server.mute(Request::new(MuteRequest { muter_id: 1, muted_id: 2, ..Default::default() })).await.unwrap();
+        server.commit(Request::new(CommitRequest::default())).await.unwrap();
+
+        let muted = server.get_muted_users(as_caller(1, Request::new(GetMutedUsersRequest { user_id: 1, version: None }))).await.unwrap();
+        assert_eq!(muted.into_inner().muted_ids, vec![2]);
+        let status = server.get_muted_users(as_caller(2, Request::new(GetMutedUsersRequest { user_id: 1, version: None }))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        let mut request = Request::new(IsMutedRequest { muter_id: 1, muted_id: 2, version: None });
+        request.extensions_mut().insert(Caller { name: "user-2".to_string(), user_id: Some(2), role: Role::Reader });
+        assert_eq!(server.is_muted(request).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+        // The public change feed leaves the mute out
+        let mut stream = server.watch_changes(Request::new(WatchChangesRequest { from_version: 0 })).await.unwrap().into_inner();
+        let kinds = [stream.next().await, stream.next().await].map(|event| event.unwrap().unwrap().kind());
+        assert_eq!(kinds, [social_network::ChangeKind::Follow, social_network::ChangeKind::Commit]);
+    }
+
+    #[tokio::test]
+    async fn test_blocks_are_private() {
+        let server = SocialNetworkServer::new();
+        let as_caller = |user_id: u64| Caller { name: format!("user-{user_id}"), user_id: Some(user_id), role: Role::Reader };
+        server.follow(Request::new(FollowRequest { follower_id: 1, followee_id: 2, ..Default::default() })).await.unwrap();
+        server.block(Request::new(BlockRequest { blocker_id: 1, blocked_id: 3, ..Default::default() })).await.unwrap();
+        server.commit(Request::new(CommitRequest::default())).await.unwrap();
+
+        let mut request = Request::new(GetBlockedUsersRequest { user_id: 1, version: None });
+        request.extensions_mut().insert(as_caller(1));
+        assert_eq!(server.get_blocked_users(request).await.unwrap().into_inner().blocked_ids, vec![3]);
+        let mut request = Request::new(GetBlockedUsersRequest { user_id: 1, version: None });
+        request.extensions_mut().insert(as_caller(3));
+        assert_eq!(server.get_blocked_users(request).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        let mut request = Request::new(IsBlockedRequest { blocker_id: 1, blocked_id: 3, version: None });
+        request.extensions_mut().insert(as_caller(3));
+        assert_eq!(server.is_blocked(request).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+        // The public change feed leaves the block out
+        let mut stream = server.watch_changes(Request::new(WatchChangesRequest { from_version: 0 })).await.unwrap().into_inner();
+        let kinds = [stream.next().await, stream.next().await].map(|event| event.unwrap().unwrap().kind());
+        assert_eq!(kinds, [social_network::ChangeKind::Follow, social_network::ChangeKind::Commit]);
+    }
+
+    #[test]
+    fn test_reflection_descriptors() {
+        let reflection = tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+            .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+            .build();
+        assert!(reflection.is_ok());
+    }
+}
@@ -0,0 +1,351 @@
+//! A social network partitioned into shards by user id, so writes for unrelated users can
+//! proceed in parallel.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{RwLock, RwLockWriteGuard};
+
+use crate::FollowInterval;
+
+/// The part of the follow graph owned by one shard
+#[derive(Debug, Default)]
+struct Shard {
+    /// Map of (`follower_id`, `followee_id`) to follow intervals, stored in the follower's shard
+    follow_intervals: HashMap<(u64, u64), Vec<FollowInterval>>,
+
+    /// Map of `follower_id` to their followees, stored in the follower's shard
+    follows: HashMap<u64, HashSet<u64>>,
+
+    /// Map of `followee_id` to their followers, stored in the followee's shard
+    is_followed: HashMap<u64, HashSet<u64>>,
+}
+
+/// A versioned social network whose `follows`, `is_followed` and `follow_intervals` are
+/// partitioned across N shards keyed by user id.
+///
+/// A follow or unfollow only locks the follower's and followee's shards, so mutations of
+/// unrelated users run in parallel. A single global version counter is shared by every shard;
+/// mutations hold the commit lock shared and `commit` holds it exclusively, so a commit never
+/// lands in the middle of a mutation.
+#[derive(Debug)]
+pub struct ShardedSocialNetwork {
+    /// Current version of the graph
+    version: AtomicU64,
+
+    /// Held shared by mutations and exclusively by commits
+    commit_lock: RwLock<()>,
+
+    shards: Vec<RwLock<Shard>>,
+}
+
+impl ShardedSocialNetwork {
+    /// Create a new social network with `shard_count` shards
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shard_count` is zero.
+    #[must_use]
+    pub fn new(shard_count: usize) -> Self {
+        assert!(shard_count > 0, "A sharded social network needs at least one shard");
+        Self {
+            version: AtomicU64::new(0),
+            commit_lock: RwLock::new(()),
+            shards: (0..shard_count).map(|_| RwLock::default()).collect(),
+        }
+    }
+
+    /// Number of shards
+    #[must_use]
+    pub const fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Follow a user
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a user tries to follow themselves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock is poisoned.
+    pub fn follow(&self, follower_id: u64, followee_id: u64) -> Result<bool, String> {
+        if follower_id == followee_id {
+            return Err("Users cannot follow themselves".to_string());
+        }
+
+        let _commit = self.commit_lock.read().unwrap();
+        let version = self.version.load(Ordering::Acquire);
+        let (mut follower_shard, mut followee_shard) = self.lock_pair(follower_id, followee_id);
+
+        follower_shard.follows.entry(follower_id).or_default().insert(followee_id);
+        let intervals = follower_shard.follow_intervals.entry((follower_id, followee_id)).or_default();
+        let was_new_follow = match intervals.last_mut() {
+            // Already following
+            Some(last) if last.follow_end == u64::MAX => false,
+            // Unfollowed and followed again in the same version
+            Some(last) if last.follow_end == version => {
+                last.follow_end = u64::MAX;
+                false
+            }
+            _ => {
+                intervals.push(FollowInterval::new(version));
+                true
+            }
+        };
+
+        followee_shard
+            .as_deref_mut()
+            .unwrap_or(&mut *follower_shard)
+            .is_followed
+            .entry(followee_id)
+            .or_default()
+            .insert(follower_id);
+
+        Ok(was_new_follow)
+    }
+
+    /// Unfollow a user
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a user tries to unfollow themselves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock is poisoned.
+    pub fn unfollow(&self, follower_id: u64, followee_id: u64) -> Result<bool, String> {
+        if follower_id == followee_id {
+            return Err("Users cannot unfollow themselves".to_string());
+        }
+
+        let _commit = self.commit_lock.read().unwrap();
+        let version = self.version.load(Ordering::Acquire);
+        let (mut follower_shard, mut followee_shard) = self.lock_pair(follower_id, followee_id);
+
+        let was_following = follower_shard
+            .follows
+            .get_mut(&follower_id)
+            .is_some_and(|followees| followees.remove(&followee_id));
+        if !was_following {
+            return Ok(false);
+        }
+
+        match follower_shard
+            .follow_intervals
+            .get_mut(&(follower_id, followee_id))
+            .and_then(|intervals| intervals.last_mut())
+        {
+            Some(last) if last.follow_end == u64::MAX => last.follow_end = version,
+            _ => return Err("Invalid follow interval".to_string()),
+        }
+
+        if let Some(followers) = followee_shard
+            .as_deref_mut()
+            .unwrap_or(&mut *follower_shard)
+            .is_followed
+            .get_mut(&followee_id)
+        {
+            followers.remove(&follower_id);
+        }
+
+        Ok(true)
+    }
+
+    /// Check if follower is following followee (use current version if not specified)
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock is poisoned.
+    #[must_use]
+    pub fn is_following(&self, follower_id: u64, followee_id: u64, version: Option<u64>) -> bool {
+        let current_version = self.current_version();
+        let version = version.unwrap_or(current_version);
+        if version > current_version {
+            return false;
+        }
+
+        self.shard(follower_id)
+            .read()
+            .unwrap()
+            .follow_intervals
+            .get(&(follower_id, followee_id))
+            .is_some_and(|intervals| intervals.iter().any(|interval| interval.is_active(version)))
+    }
+
+    /// Commit the current state of the graph
+    ///
+    /// # Panics
+    ///
+    /// Panics if the commit lock is poisoned.
+    pub fn commit(&self) -> u64 {
+        let _commit = self.commit_lock.write().unwrap();
+        self.version.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Get the current version
+    #[must_use]
+    pub fn current_version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Get all followers of a user, sorted by user id
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock is poisoned.
+    #[must_use]
+    pub fn get_followers(&self, user_id: u64) -> Vec<u64> {
+        let mut followers: Vec<u64> = self
+            .shard(user_id)
+            .read()
+            .unwrap()
+            .is_followed
+            .get(&user_id)
+            .map(|f| f.iter().copied().collect())
+            .unwrap_or_default();
+        followers.sort_unstable();
+        followers
+    }
+
+    /// Get all followees of a user, sorted by user id
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock is poisoned.
+    #[must_use]
+    pub fn get_followees(&self, user_id: u64) -> Vec<u64> {
+        let mut followees: Vec<u64> = self
+            .shard(user_id)
+            .read()
+            .unwrap()
+            .follows
+            .get(&user_id)
+            .map(|f| f.iter().copied().collect())
+            .unwrap_or_default();
+        followees.sort_unstable();
+        followees
+    }
+
+    /// Get the current number of followers of a user
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock is poisoned.
+    #[must_use]
+    pub fn follower_count(&self, user_id: u64) -> u64 {
+        self.shard(user_id).read().unwrap().is_followed.get(&user_id).map_or(0, HashSet::len) as u64
+    }
+
+    /// Get the current number of users a user follows
+    ///
+    /// # Panics
+    ///
+    /// Panics if a lock is poisoned.
+    #[must_use]
+    pub fn followee_count(&self, user_id: u64) -> u64 {
+        self.shard(user_id).read().unwrap().follows.get(&user_id).map_or(0, HashSet::len) as u64
+    }
+
+    fn shard_index(&self, user_id: u64) -> usize {
+        // The remainder is below the shard count, so it always fits in a usize
+        usize::try_from(user_id % self.shards.len() as u64).unwrap_or_default()
+    }
+
+    fn shard(&self, user_id: u64) -> &RwLock<Shard> {
+        &self.shards[self.shard_index(user_id)]
+    }
+
+    /// Write-lock the follower's shard and, if it is a different one, the followee's shard.
+    /// Shards are always locked in index order so concurrent mutations cannot deadlock.
+    fn lock_pair(
+        &self,
+        follower_id: u64,
+        followee_id: u64,
+    ) -> (RwLockWriteGuard<'_, Shard>, Option<RwLockWriteGuard<'_, Shard>>) {
+        let follower_index = self.shard_index(follower_id);
+        let followee_index = self.shard_index(followee_id);
+
+        match follower_index.cmp(&followee_index) {
+            std::cmp::Ordering::Equal => (self.shards[follower_index].write().unwrap(), None),
+            std::cmp::Ordering::Less => {
+                let follower_shard = self.shards[follower_index].write().unwrap();
+                let followee_shard = self.shards[followee_index].write().unwrap();
+                (follower_shard, Some(followee_shard))
+            }
+            std::cmp::Ordering::Greater => {
+                let followee_shard = self.shards[followee_index].write().unwrap();
+                let follower_shard = self.shards[follower_index].write().unwrap();
+                (follower_shard, Some(followee_shard))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_sharded_follow_and_unfollow() {
+        let network = ShardedSocialNetwork::new(4);
+
+        assert!(network.follow(1, 2).unwrap());
+        assert!(!network.follow(1, 2).unwrap());
+        assert!(network.follow(1, 5).unwrap()); // same shard as 1
+        assert!(network.follow(3, 2).unwrap());
+        assert!(network.follow(1, 1).is_err());
+        let v1 = network.commit();
+
+        assert_eq!(network.get_followees(1), vec![2, 5]);
+        assert_eq!(network.get_followers(2), vec![1, 3]);
+        assert_eq!(network.follower_count(2), 2);
+
+        assert!(network.unfollow(1, 2).unwrap());
+        assert!(!network.unfollow(1, 2).unwrap());
+        assert!(network.unfollow(1, 1).is_err());
+        let v2 = network.commit();
+
+        assert_eq!(network.get_followers(2), vec![3]);
+        assert_eq!(network.followee_count(1), 1);
+        assert!(network.is_following(1, 2, Some(v1)));
+        assert!(!network.is_following(1, 2, Some(v2)));
+        assert!(!network.is_following(1, 2, Some(999)));
+
+        // Refollow starts a new interval
+        assert!(network.follow(1, 2).unwrap());
+        assert!(network.is_following(1, 2, None));
+    }
+
+    #[test]
+    fn test_sharded_concurrent_writers() {
+        let network = ShardedSocialNetwork::new(8);
+
+        thread::scope(|scope| {
+            for writer in 0..8 {
+                let network = &network;
+                scope.spawn(move || {
+                    for followee_id in 100..200 {
+                        network.follow(writer, followee_id).unwrap();
+                    }
+                    network.commit();
+                    for followee_id in 100..150 {
+                        network.unfollow(writer, followee_id).unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(network.current_version(), 8);
+        for writer in 0..8 {
+            assert_eq!(network.followee_count(writer), 50);
+        }
+        for followee_id in 100..150 {
+            assert_eq!(network.follower_count(followee_id), 0);
+        }
+        for followee_id in 150..200 {
+            assert_eq!(network.get_followers(followee_id), (0..8).collect::<Vec<_>>());
+        }
+    }
+}
@@ -1,8 +1,33 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::ops::Bound;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
 
 //use rand::{distributions::uniform::SampleUniform, seq::index::sample};
 
+mod analytics;
+pub mod auth;
+mod blocks;
+pub mod config;
+pub mod idempotency;
+pub mod metrics;
+mod mutes;
+pub mod persistence;
+pub mod policy;
+pub mod rate_limit;
+mod relationships;
+pub mod request_id;
 pub mod server;
+mod sharded;
+pub mod tls;
+
+pub use analytics::GraphStats;
+pub use policy::FollowPolicy;
+pub use sharded::ShardedSocialNetwork;
+use relationships::Relationships;
 
 #[derive(Debug, Clone)]
 pub struct FollowInterval {
@@ -25,6 +50,156 @@ impl FollowInterval {
     }
 }
 
+/// Order in which paged follower / followee listings are returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FollowOrder {
+    /// Ascending user id
+    #[default]
+    UserId,
+    /// Ascending version at which the follow started, ties broken by user id
+    FollowStart,
+}
+
+/// Position of the last entry of a page; pass it back to fetch the next page.
+///
+/// `sort_key` is the user id for [`FollowOrder::UserId`] and the follow start version for
+/// [`FollowOrder::FollowStart`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PageCursor {
+    pub sort_key: u64,
+    pub user_id: u64,
+}
+
+/// A page of follower or followee ids
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FollowPage {
+    pub user_ids: Vec<u64>,
+
+    /// Cursor for the next page, `None` once the listing is exhausted
+    pub next_cursor: Option<PageCursor>,
+}
+
+/// Current followers or followees of one user, kept sorted in both orders so a page is a range
+/// scan from the cursor
+#[derive(Debug, Default)]
+struct PageIndex {
+    by_user_id: BTreeSet<PageCursor>,
+    by_follow_start: BTreeSet<PageCursor>,
+}
+
+impl PageIndex {
+    fn insert(&mut self, user_id: u64, follow_start: u64) {
+        self.by_user_id.insert(PageCursor { sort_key: user_id, user_id });
+        self.by_follow_start.insert(PageCursor { sort_key: follow_start, user_id });
+    }
+
+    fn remove(&mut self, user_id: u64, follow_start: u64) {
+        self.by_user_id.remove(&PageCursor { sort_key: user_id, user_id });
+        self.by_follow_start.remove(&PageCursor { sort_key: follow_start, user_id });
+    }
+
+    fn is_empty(&self) -> bool {
+        self.by_user_id.is_empty()
+    }
+
+    /// The first `limit` entries after `cursor`
+    fn page(&self, order: FollowOrder, cursor: Option<PageCursor>, limit: usize) -> FollowPage {
+        let keys = match order {
+            FollowOrder::UserId => &self.by_user_id,
+            FollowOrder::FollowStart => &self.by_follow_start,
+        };
+        let start = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+        let limit = limit.max(1);
+        let mut page: Vec<PageCursor> = keys.range((start, Bound::Unbounded)).take(limit + 1).copied().collect();
+
+        let has_more = page.len() > limit;
+        page.truncate(limit);
+        FollowPage {
+            next_cursor: if has_more { page.last().copied() } else { None },
+            user_ids: page.into_iter().map(|key| key.user_id).collect(),
+        }
+    }
+}
+
+/// A single follow or unfollow in a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mutation {
+    Follow { follower_id: u64, followee_id: u64 },
+    Unfollow { follower_id: u64, followee_id: u64 },
+}
+
+/// Limit on the new follows a user can make within a sliding time window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FollowQuota {
+    pub max_follows: usize,
+    pub window: Duration,
+}
+
+/// Why [`SocialNetwork::follow`] rejected a follow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FollowError {
+    /// Users cannot follow themselves
+    SelfFollow,
+    /// One of the users has blocked the other
+    Blocked { blocker_id: u64, blocked_id: u64 },
+    /// A [`FollowPolicy`] rejected the follow, with its explanation
+    Policy(String),
+    /// The follower has used up their [`FollowQuota`]
+    QuotaExceeded { follower_id: u64, quota: FollowQuota },
+}
+
+impl fmt::Display for FollowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SelfFollow => write!(f, "Users cannot follow themselves"),
+            Self::Blocked { blocker_id, blocked_id } => write!(f, "User {blocker_id} has blocked user {blocked_id}"),
+            Self::Policy(reason) => f.write_str(reason),
+            Self::QuotaExceeded { follower_id, quota } => write!(
+                f,
+                "User {follower_id} has reached the limit of {} new follows per {:?}",
+                quota.max_follows, quota.window
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FollowError {}
+
+impl From<FollowError> for String {
+    fn from(error: FollowError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Kind of change recorded in the change log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Follow,
+    Unfollow,
+    Commit,
+    Block,
+    Unblock,
+    Mute,
+    Unmute,
+}
+
+/// An entry in the change log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    /// Position of the event in the change log, starting at 0
+    pub sequence: u64,
+    pub kind: ChangeKind,
+
+    /// Version the follow / unfollow was made at, or the version sealed by a commit
+    pub version: u64,
+
+    /// Users involved in a follow / unfollow (0 for a commit). For a block / unblock or a
+    /// mute / unmute, the follower is the blocker or muter and the followee the other user.
+    pub follower_id: u64,
+    pub followee_id: u64,
+}
+
 /// Represents a social network graph with versioning capabilities
 #[derive(Debug)]
 pub struct SocialNetwork {
@@ -39,6 +214,45 @@ pub struct SocialNetwork {
 
     /// Map of user_id to their followees. 
     is_followed: HashMap<u64, HashSet<u64>>,
+
+    /// Map of `user_id` to everyone who has ever followed them. Used to answer historical queries
+    /// without scanning every follow interval.
+    follower_history: HashMap<u64, HashSet<u64>>,
+
+    /// Map of `user_id` to everyone they have ever followed.
+    followee_history: HashMap<u64, HashSet<u64>>,
+
+    /// Every follow, unfollow and commit in the order they were applied. Versions are non-decreasing.
+    changes: Vec<ChangeEvent>,
+
+    /// Number of users who have ever followed or been followed
+    user_count: u64,
+
+    /// Number of follows currently active
+    edge_count: u64,
+
+    /// Total number of follow intervals
+    interval_count: u64,
+
+    /// Map of `user_id` to their current followers, sorted for paging
+    follower_pages: HashMap<u64, PageIndex>,
+
+    /// Map of `user_id` to their current followees, sorted for paging
+    followee_pages: HashMap<u64, PageIndex>,
+
+    /// Blocks by (`blocker_id`, `blocked_id`)
+    blocks: Relationships,
+
+    /// Mutes by (`muter_id`, `muted_id`)
+    mutes: Relationships,
+
+    /// Rules a new follow must pass, in the order they were added
+    follow_policies: Vec<Box<dyn FollowPolicy>>,
+
+    follow_quota: Option<FollowQuota>,
+
+    /// Map of `user_id` to when they made their recent new follows, oldest first, for the follow quota
+    recent_follows: HashMap<u64, VecDeque<Instant>>,
 }
 
 impl SocialNetwork {
@@ -49,17 +263,97 @@ impl SocialNetwork {
             follow_intervals: HashMap::new(),
             follows: HashMap::new(),
             is_followed: HashMap::new(),
+            follower_history: HashMap::new(),
+            followee_history: HashMap::new(),
+            changes: Vec::new(),
+            user_count: 0,
+            edge_count: 0,
+            interval_count: 0,
+            follower_pages: HashMap::new(),
+            followee_pages: HashMap::new(),
+            blocks: Relationships::default(),
+            mutes: Relationships::default(),
+            follow_policies: Vec::new(),
+            follow_quota: None,
+            recent_follows: HashMap::new(),
+        }
+    }
+
+    /// Add a rule that every follow creating a new edge must pass
+    pub fn add_follow_policy(&mut self, policy: impl FollowPolicy + 'static) {
+        self.follow_policies.push(Box::new(policy));
+    }
+
+    /// Remove every follow policy
+    pub fn clear_follow_policies(&mut self) {
+        self.follow_policies.clear();
+    }
+
+    /// Limit how many new follows each user can make per time window, or lift the limit.
+    ///
+    /// Follows made before the quota was set do not count against it.
+    pub fn set_follow_quota(&mut self, quota: Option<FollowQuota>) {
+        self.follow_quota = quota;
+        self.recent_follows.clear();
+    }
+
+    /// Fail if `follower_id` has used up the follow quota, forgetting follows that have left
+    /// the window
+    fn check_follow_quota(&mut self, follower_id: u64) -> Result<(), FollowError> {
+        let Some(quota) = self.follow_quota else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let recent = self.recent_follows.entry(follower_id).or_default();
+        while recent.front().is_some_and(|&at| now.duration_since(at) >= quota.window) {
+            recent.pop_front();
+        }
+        if recent.len() >= quota.max_follows {
+            return Err(FollowError::QuotaExceeded { follower_id, quota });
+        }
+        Ok(())
+    }
+
+    /// Count a new follow by `follower_id` against the follow quota
+    fn record_follow_quota(&mut self, follower_id: u64) {
+        if self.follow_quota.is_some() {
+            self.recent_follows.entry(follower_id).or_default().push_back(Instant::now());
         }
     }
 
     /// Follow a user
-    pub fn follow(&mut self, follower_id: u64, followee_id: u64) -> Result<bool, String> {
+    ///
+    /// # Errors
+    ///
+    /// Fails without changing anything if the users are the same, either has blocked the other,
+    /// or a new follow breaks a follow policy or the follow quota.
+    #[instrument(level = "debug", skip(self), fields(version = self.version), ret)]
+    pub fn follow(&mut self, follower_id: u64, followee_id: u64) -> Result<bool, FollowError> {
         if follower_id == followee_id {
-            return Err("Users cannot follow themselves".to_string());
+            return Err(FollowError::SelfFollow);
+        }
+        if let Some((blocker_id, blocked_id)) = self.active_block_between(follower_id, followee_id) {
+            return Err(FollowError::Blocked { blocker_id, blocked_id });
+        }
+        if !self.follows_now(follower_id, followee_id) {
+            for policy in &self.follow_policies {
+                policy.check(self, follower_id, followee_id).map_err(FollowError::Policy)?;
+            }
+            // Undoing an unfollow made in the current version is not a new follow
+            let reopens = self
+                .follow_intervals
+                .get(&(follower_id, followee_id))
+                .and_then(|intervals| intervals.last())
+                .is_some_and(|last| last.follow_end == self.version);
+            if !reopens {
+                self.check_follow_quota(follower_id)?;
+            }
         }
 
         // Add to follows
-        self.follows.entry(follower_id).or_insert_with(HashSet::new).insert(followee_id);
+        if self.follows.entry(follower_id).or_insert_with(HashSet::new).insert(followee_id) {
+            self.edge_count += 1;
+        }
 
         // Add to is_followed
         self.is_followed.entry(followee_id).or_insert_with(HashSet::new).insert(follower_id);
@@ -73,6 +367,9 @@ impl SocialNetwork {
             } else if last.follow_end == self.version {
                 // this is the case where a unfollows b, then they follow again in the same version
                 last.follow_end = u64::MAX;
+                let follow_start = last.follow_start;
+                self.index_follow(follower_id, followee_id, follow_start);
+                self.record_change(ChangeKind::Follow, follower_id, followee_id);
                 return Ok(false);
             } else if last.follow_end < self.version {
                 // do nothing
@@ -85,11 +382,24 @@ impl SocialNetwork {
             .entry((follower_id, followee_id))
             .or_insert_with(Vec::new)
             .push(interval);
+        self.interval_count += 1;
+        self.index_follow(follower_id, followee_id, self.version);
+
+        for user_id in [follower_id, followee_id] {
+            if !self.follower_history.contains_key(&user_id) && !self.followee_history.contains_key(&user_id) {
+                self.user_count += 1;
+            }
+        }
+        self.follower_history.entry(followee_id).or_default().insert(follower_id);
+        self.followee_history.entry(follower_id).or_default().insert(followee_id);
+        self.record_change(ChangeKind::Follow, follower_id, followee_id);
+        self.record_follow_quota(follower_id);
 
         Ok(true)
     }
 
     /// Unfollow a user
+    #[instrument(level = "debug", skip(self), fields(version = self.version), ret)]
     pub fn unfollow(&mut self, follower_id: u64, followee_id: u64) -> Result<bool, String> {
         if follower_id == followee_id {
             return Err("Users cannot unfollow themselves".to_string());
@@ -103,9 +413,11 @@ impl SocialNetwork {
 
         // Remove from follows
         self.follows.get_mut(&follower_id).unwrap().remove(&followee_id);
+        self.edge_count -= 1;
 
         // Remove from is_followed
         self.is_followed.get_mut(&followee_id).unwrap().remove(&follower_id);
+        self.unindex_follow(follower_id, followee_id);
 
         // Find the follow intervals for the follower and followee
         let follow_intervals = self.follow_intervals.get_mut(&(follower_id, followee_id));
@@ -121,9 +433,11 @@ impl SocialNetwork {
                     Some(interval) => {
                         if interval.follow_end == u64::MAX {
                             interval.follow_end = self.version;
+                            self.record_change(ChangeKind::Unfollow, follower_id, followee_id);
                             return Ok(true);
                         } else if interval.follow_end == self.version {
                             interval.follow_end = u64::MAX;
+                            self.record_change(ChangeKind::Unfollow, follower_id, followee_id);
                             return Ok(true);
                         } else {
                             return Err("Invalid follow interval".to_string());
@@ -140,6 +454,43 @@ impl SocialNetwork {
         }
     }
 
+    /// Add a follow that has just become active to the page indexes
+    fn index_follow(&mut self, follower_id: u64, followee_id: u64, follow_start: u64) {
+        self.follower_pages.entry(followee_id).or_default().insert(follower_id, follow_start);
+        self.followee_pages.entry(follower_id).or_default().insert(followee_id, follow_start);
+    }
+
+    /// Remove a follow that is about to end from the page indexes
+    fn unindex_follow(&mut self, follower_id: u64, followee_id: u64) {
+        let follow_start = self.follow_start(follower_id, followee_id, None);
+        for (pages, user_id, other_id) in [
+            (&mut self.follower_pages, followee_id, follower_id),
+            (&mut self.followee_pages, follower_id, followee_id),
+        ] {
+            if let Some(index) = pages.get_mut(&user_id) {
+                index.remove(other_id, follow_start);
+                if index.is_empty() {
+                    pages.remove(&user_id);
+                }
+            }
+        }
+    }
+
+    /// Apply a batch of follows / unfollows in order at the current version
+    ///
+    /// Returns the result `follow` / `unfollow` gave for each mutation; a failed mutation does
+    /// not stop the rest of the batch.
+    #[instrument(level = "debug", skip_all, fields(mutations = mutations.len(), version = self.version))]
+    pub fn apply_batch(&mut self, mutations: &[Mutation]) -> Vec<Result<bool, String>> {
+        mutations
+            .iter()
+            .map(|mutation| match *mutation {
+                Mutation::Follow { follower_id, followee_id } => self.follow(follower_id, followee_id).map_err(String::from),
+                Mutation::Unfollow { follower_id, followee_id } => self.unfollow(follower_id, followee_id),
+            })
+            .collect()
+    }
+
     /// Check if follower is following followee (use current version if not specified)
     pub fn is_following(&self, follower_id: u64, followee_id: u64, version: Option<u64>) -> bool {
         let version = version.unwrap_or(self.version);
@@ -158,32 +509,275 @@ impl SocialNetwork {
         }
     }
 
+    /// Check the live follow set, including uncommitted changes, for a follow
+    pub(crate) fn follows_now(&self, follower_id: u64, followee_id: u64) -> bool {
+        self.follows.get(&follower_id).is_some_and(|followees| followees.contains(&followee_id))
+    }
+
+    /// Version at which the follower last unfollowed the followee, unless they follow them now
+    #[must_use]
+    pub fn last_unfollow_version(&self, follower_id: u64, followee_id: u64) -> Option<u64> {
+        let last = self.follow_intervals.get(&(follower_id, followee_id))?.last()?;
+        (last.follow_end != u64::MAX).then_some(last.follow_end)
+    }
+
+    /// Which of `candidates` a user follows at a version (use current version if not specified), in input order
+    #[must_use]
+    pub fn follows_any(&self, user_id: u64, candidates: &[u64], version: Option<u64>) -> Vec<u64> {
+        candidates
+            .iter()
+            .copied()
+            .filter(|&candidate| self.is_following(user_id, candidate, version))
+            .collect()
+    }
+
+    /// Which of `candidates` follow a user at a version (use current version if not specified), in input order
+    #[must_use]
+    pub fn followed_by_many(&self, user_id: u64, candidates: &[u64], version: Option<u64>) -> Vec<u64> {
+        candidates
+            .iter()
+            .copied()
+            .filter(|&candidate| self.is_following(candidate, user_id, version))
+            .collect()
+    }
+
     /// Commit the current state of the graph
+    #[instrument(level = "debug", skip(self), fields(version = self.version), ret)]
     pub fn commit(&mut self) -> u64 {
+        self.record_change(ChangeKind::Commit, 0, 0);
         self.version += 1;
         self.version
     }
 
+    /// Get every change in the change log, in the order they were applied
+    #[must_use]
+    pub fn changes(&self) -> &[ChangeEvent] {
+        &self.changes
+    }
+
+    /// Number of users who have ever followed or been followed
+    #[must_use]
+    pub const fn user_count(&self) -> u64 {
+        self.user_count
+    }
+
+    /// Number of follows currently active
+    #[must_use]
+    pub const fn edge_count(&self) -> u64 {
+        self.edge_count
+    }
+
+    /// Total number of follow intervals, the unit of storage for the version history
+    #[must_use]
+    pub const fn interval_count(&self) -> u64 {
+        self.interval_count
+    }
+
+    /// Whether any follow or unfollow has been made since the last commit
+    #[must_use]
+    pub fn has_uncommitted_changes(&self) -> bool {
+        self.changes.last().is_some_and(|change| change.kind != ChangeKind::Commit)
+    }
+
+    /// Get the changes made at or after a version, including the commit that sealed it
+    #[must_use]
+    pub fn changes_since(&self, version: u64) -> &[ChangeEvent] {
+        let start = self.changes.partition_point(|change| change.version < version);
+        &self.changes[start..]
+    }
+
+    /// Append a change made at the current version to the change log
+    fn record_change(&mut self, kind: ChangeKind, follower_id: u64, followee_id: u64) {
+        self.changes.push(ChangeEvent {
+            sequence: self.changes.len() as u64,
+            kind,
+            version: self.version,
+            follower_id,
+            followee_id,
+        });
+    }
+
     /// Get the current version
     pub fn current_version(&self) -> u64 {
         self.version
     }
 
-    /// Get all followers of a user
+    /// Get all followers of a user, sorted by user id
     pub fn get_followers(&self, user_id: u64) -> Vec<u64> {
-        self.is_followed
+        let mut followers: Vec<u64> = self.is_followed
             .get(&user_id)
             .map(|f| f.iter().copied().collect())
-            .unwrap_or_default()
+            .unwrap_or_default();
+        followers.sort_unstable();
+        followers
     }
 
-    /// Get all followees of a user
+    /// Get all followees of a user, sorted by user id
     pub fn get_followees(&self, user_id: u64) -> Vec<u64> {
-        self.follows
+        let mut followees: Vec<u64> = self.follows
             .get(&user_id)
             .map(|f| f.iter().copied().collect())
+            .unwrap_or_default();
+        followees.sort_unstable();
+        followees
+    }
+
+    /// Get a page of at most `limit` followers of a user (use current version if not specified)
+    ///
+    /// Pass the previous page's `next_cursor` as `cursor` to continue the listing. Current pages
+    /// are a range scan of a sorted index; historical pages sort the followers at that version.
+    #[must_use]
+    pub fn get_followers_page(
+        &self,
+        user_id: u64,
+        version: Option<u64>,
+        order: FollowOrder,
+        cursor: Option<PageCursor>,
+        limit: usize,
+    ) -> FollowPage {
+        if version.is_none() {
+            return self.follower_pages.get(&user_id).map_or_else(FollowPage::default, |index| index.page(order, cursor, limit));
+        }
+        let keys = self
+            .followers_at(user_id, version)
+            .into_iter()
+            .map(|follower_id| PageCursor {
+                sort_key: match order {
+                    FollowOrder::UserId => follower_id,
+                    FollowOrder::FollowStart => self.follow_start(follower_id, user_id, version),
+                },
+                user_id: follower_id,
+            })
+            .collect();
+        paginate(keys, cursor, limit)
+    }
+
+    /// Get a page of at most `limit` followees of a user (use current version if not specified)
+    ///
+    /// Pass the previous page's `next_cursor` as `cursor` to continue the listing.
+    #[must_use]
+    pub fn get_followees_page(
+        &self,
+        user_id: u64,
+        version: Option<u64>,
+        order: FollowOrder,
+        cursor: Option<PageCursor>,
+        limit: usize,
+    ) -> FollowPage {
+        if version.is_none() {
+            return self.followee_pages.get(&user_id).map_or_else(FollowPage::default, |index| index.page(order, cursor, limit));
+        }
+        let keys = self
+            .followees_at(user_id, version)
+            .into_iter()
+            .map(|followee_id| PageCursor {
+                sort_key: match order {
+                    FollowOrder::UserId => followee_id,
+                    FollowOrder::FollowStart => self.follow_start(user_id, followee_id, version),
+                },
+                user_id: followee_id,
+            })
+            .collect();
+        paginate(keys, cursor, limit)
+    }
+
+    /// Get the followers of a user at a version (use current state if not specified), in no particular order
+    #[must_use]
+    pub fn followers_at(&self, user_id: u64, version: Option<u64>) -> Vec<u64> {
+        let Some(version) = version else {
+            return self.is_followed.get(&user_id).map(|f| f.iter().copied().collect()).unwrap_or_default();
+        };
+        self.follower_history
+            .get(&user_id)
+            .map(|f| {
+                f.iter()
+                    .copied()
+                    .filter(|&follower_id| self.is_following(follower_id, user_id, Some(version)))
+                    .collect()
+            })
             .unwrap_or_default()
     }
+
+    /// Get the followees of a user at a version (use current state if not specified), in no particular order
+    #[must_use]
+    pub fn followees_at(&self, user_id: u64, version: Option<u64>) -> Vec<u64> {
+        let Some(version) = version else {
+            return self.follows.get(&user_id).map(|f| f.iter().copied().collect()).unwrap_or_default();
+        };
+        self.followee_history
+            .get(&user_id)
+            .map(|f| {
+                f.iter()
+                    .copied()
+                    .filter(|&followee_id| self.is_following(user_id, followee_id, Some(version)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Version at which the follow active at a version (or the latest follow) started
+    fn follow_start(&self, follower_id: u64, followee_id: u64, version: Option<u64>) -> u64 {
+        self.follow_intervals
+            .get(&(follower_id, followee_id))
+            .and_then(|intervals| {
+                version.map_or_else(
+                    || intervals.last(),
+                    |version| intervals.iter().rev().find(|interval| interval.is_active(version)),
+                )
+            })
+            .map_or(0, |interval| interval.follow_start)
+    }
+
+    /// Get the number of followers of a user (use current version if not specified)
+    ///
+    /// The current count is O(1); historical counts only look at users who have ever
+    /// followed `user_id`.
+    #[must_use]
+    pub fn follower_count(&self, user_id: u64, version: Option<u64>) -> u64 {
+        let Some(version) = version else {
+            return self.is_followed.get(&user_id).map_or(0, HashSet::len) as u64;
+        };
+        self.follower_history.get(&user_id).map_or(0, |followers| {
+            followers
+                .iter()
+                .filter(|&&follower_id| self.is_following(follower_id, user_id, Some(version)))
+                .count() as u64
+        })
+    }
+
+    /// Get the number of users a user follows (use current version if not specified)
+    #[must_use]
+    pub fn followee_count(&self, user_id: u64, version: Option<u64>) -> u64 {
+        let Some(version) = version else {
+            return self.follows.get(&user_id).map_or(0, HashSet::len) as u64;
+        };
+        self.followee_history.get(&user_id).map_or(0, |followees| {
+            followees
+                .iter()
+                .filter(|&&followee_id| self.is_following(user_id, followee_id, Some(version)))
+                .count() as u64
+        })
+    }
+}
+
+/// Sort `keys`, skip everything up to and including `cursor` and keep the first `limit` entries
+fn paginate(mut keys: Vec<PageCursor>, cursor: Option<PageCursor>, limit: usize) -> FollowPage {
+    let limit = limit.max(1);
+    if let Some(cursor) = cursor {
+        keys.retain(|key| *key > cursor);
+    }
+
+    let has_more = keys.len() > limit;
+    if has_more {
+        keys.select_nth_unstable(limit);
+        keys.truncate(limit);
+    }
+    keys.sort_unstable();
+
+    FollowPage {
+        next_cursor: if has_more { keys.last().copied() } else { None },
+        user_ids: keys.into_iter().map(|key| key.user_id).collect(),
+    }
 }
 
 #[cfg(test)]
@@ -331,4 +925,211 @@ mod tests {
         assert!(network.follow(1, 2).unwrap());
         assert!(network.is_following(1, 2, None));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_follower_and_followee_counts() {
+        let mut network = SocialNetwork::new();
+
+        network.follow(1, 3).unwrap();
+        network.follow(2, 3).unwrap();
+        network.follow(3, 1).unwrap();
+        let v1 = network.commit();
+
+        assert_eq!(network.follower_count(3, None), 2);
+        assert_eq!(network.followee_count(3, None), 1);
+        assert_eq!(network.follower_count(999, None), 0);
+
+        network.unfollow(1, 3).unwrap();
+        let v2 = network.commit();
+
+        assert_eq!(network.follower_count(3, None), 1);
+        assert_eq!(network.followee_count(1, None), 0);
+
+        // Historical counts
+        assert_eq!(network.follower_count(3, Some(v1)), 2);
+        assert_eq!(network.follower_count(3, Some(v2)), 1);
+        assert_eq!(network.followee_count(1, Some(v1)), 1);
+        assert_eq!(network.followee_count(1, Some(v2)), 0);
+        assert_eq!(network.follower_count(3, Some(999)), 0);
+    }
+
+    #[test]
+    fn test_graph_size_counters() {
+        let mut network = SocialNetwork::new();
+
+        network.follow(1, 2).unwrap();
+        network.follow(1, 3).unwrap();
+        network.follow(1, 2).unwrap(); // already following
+        network.commit();
+        network.unfollow(1, 2).unwrap();
+        network.follow(1, 2).unwrap(); // reopens the interval in the same version
+        network.unfollow(1, 3).unwrap();
+        network.commit();
+        network.follow(1, 3).unwrap(); // new interval
+
+        assert_eq!(network.user_count(), 3);
+        assert_eq!(network.edge_count(), 2);
+        assert_eq!(network.interval_count(), 3);
+    }
+
+    #[test]
+    fn test_followers_page() {
+        let mut network = SocialNetwork::new();
+
+        // Followers 5, 3, 9, 1 start following user 100 at versions 0, 1, 2, 3
+        for follower_id in [5, 3, 9, 1] {
+            network.follow(follower_id, 100).unwrap();
+            network.commit();
+        }
+        let v = network.current_version();
+
+        let first = network.get_followers_page(100, None, FollowOrder::UserId, None, 3);
+        assert_eq!(first.user_ids, vec![1, 3, 5]);
+        let second = network.get_followers_page(100, None, FollowOrder::UserId, first.next_cursor, 3);
+        assert_eq!(second.user_ids, vec![9]);
+        assert_eq!(second.next_cursor, None);
+
+        let first = network.get_followers_page(100, None, FollowOrder::FollowStart, None, 2);
+        assert_eq!(first.user_ids, vec![5, 3]);
+        let second = network.get_followers_page(100, None, FollowOrder::FollowStart, first.next_cursor, 2);
+        assert_eq!(second.user_ids, vec![9, 1]);
+        assert_eq!(second.next_cursor, None);
+
+        // Historical pages only include followers at that version
+        network.unfollow(3, 100).unwrap();
+        network.commit();
+        let page = network.get_followers_page(100, Some(2), FollowOrder::UserId, None, 10);
+        assert_eq!(page.user_ids, vec![3, 5, 9]);
+        let page = network.get_followers_page(100, Some(v + 1), FollowOrder::UserId, None, 10);
+        assert_eq!(page.user_ids, vec![1, 5, 9]);
+        assert_eq!(network.get_followers(100), vec![1, 5, 9]);
+
+        // The current index follows unfollows and re-follows, which start a new follow
+        let page = network.get_followers_page(100, None, FollowOrder::UserId, None, 10);
+        assert_eq!(page.user_ids, vec![1, 5, 9]);
+        network.follow(3, 100).unwrap();
+        let page = network.get_followers_page(100, None, FollowOrder::FollowStart, None, 10);
+        assert_eq!(page.user_ids, vec![5, 9, 1, 3]);
+    }
+
+    #[test]
+    fn test_followees_page() {
+        let mut network = SocialNetwork::new();
+
+        network.follow(1, 30).unwrap();
+        network.commit();
+        network.follow(1, 20).unwrap();
+        network.follow(1, 10).unwrap();
+        network.commit();
+
+        let page = network.get_followees_page(1, None, FollowOrder::FollowStart, None, 10);
+        assert_eq!(page.user_ids, vec![30, 10, 20]);
+        assert_eq!(page.next_cursor, None);
+
+        let page = network.get_followees_page(1, None, FollowOrder::UserId, None, 2);
+        assert_eq!(page.user_ids, vec![10, 20]);
+        let page = network.get_followees_page(1, None, FollowOrder::UserId, page.next_cursor, 2);
+        assert_eq!(page.user_ids, vec![30]);
+
+        assert!(network.get_followees_page(999, None, FollowOrder::UserId, None, 10).user_ids.is_empty());
+    }
+
+    #[test]
+    fn test_change_log() {
+        let mut network = SocialNetwork::new();
+
+        network.follow(1, 2).unwrap();
+        network.follow(1, 2).unwrap(); // already following, not a change
+        network.commit();
+        network.unfollow(1, 2).unwrap();
+        network.unfollow(1, 2).unwrap(); // not following, not a change
+        network.follow(3, 4).unwrap();
+        network.commit();
+
+        let kinds: Vec<(ChangeKind, u64)> = network.changes().iter().map(|c| (c.kind, c.version)).collect();
+        assert_eq!(kinds, vec![
+            (ChangeKind::Follow, 0),
+            (ChangeKind::Commit, 0),
+            (ChangeKind::Unfollow, 1),
+            (ChangeKind::Follow, 1),
+            (ChangeKind::Commit, 1),
+        ]);
+        assert!(network.changes().iter().enumerate().all(|(i, c)| c.sequence == i as u64));
+
+        // Resuming from a version replays everything made at or after it
+        let since = network.changes_since(1);
+        assert_eq!(since.len(), 3);
+        assert_eq!((since[0].follower_id, since[0].followee_id), (1, 2));
+        assert!(network.changes_since(2).is_empty());
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let mut network = SocialNetwork::new();
+        network.follow(1, 3).unwrap();
+
+        let results = network.apply_batch(&[
+            Mutation::Follow { follower_id: 1, followee_id: 2 },
+            Mutation::Follow { follower_id: 1, followee_id: 1 },
+            Mutation::Unfollow { follower_id: 1, followee_id: 3 },
+            Mutation::Unfollow { follower_id: 1, followee_id: 4 },
+        ]);
+        assert_eq!(results[0], Ok(true));
+        assert!(results[1].is_err());
+        assert_eq!(results[2], Ok(true));
+        assert_eq!(results[3], Ok(false));
+
+        network.commit();
+        assert_eq!(network.get_followees(1), vec![2]);
+    }
+
+    #[test]
+    fn test_follows_any_and_followed_by_many() {
+        let mut network = SocialNetwork::new();
+        network.follow(1, 2).unwrap();
+        network.follow(1, 4).unwrap();
+        network.follow(3, 1).unwrap();
+        let v1 = network.commit();
+        network.commit();
+        network.unfollow(1, 4).unwrap();
+        network.follow(5, 1).unwrap();
+        network.commit();
+
+        assert_eq!(network.follows_any(1, &[4, 3, 2], None), vec![2]);
+        assert_eq!(network.follows_any(1, &[4, 3, 2], Some(v1)), vec![4, 2]);
+        assert_eq!(network.followed_by_many(1, &[5, 2, 3], None), vec![5, 3]);
+        assert_eq!(network.followed_by_many(1, &[5, 2, 3], Some(v1)), vec![3]);
+        assert!(network.follows_any(1, &[], None).is_empty());
+    }
+
+    #[test]
+    fn test_follow_quota() {
+        let mut network = SocialNetwork::new();
+        network.follow(1, 9).unwrap();
+        network.set_follow_quota(Some(FollowQuota { max_follows: 2, window: Duration::from_millis(100) }));
+
+        assert!(network.follow(1, 2).unwrap());
+        // Following someone already followed is not a new follow
+        assert!(!network.follow(1, 2).unwrap());
+        assert!(network.follow(1, 3).unwrap());
+        assert!(network.follow(1, 4).is_err());
+        assert!(!network.is_following(1, 4, None));
+
+        // At the limit, following again or undoing an unfollow from the same version is allowed
+        // and does not count against the quota
+        assert!(!network.follow(1, 2).unwrap());
+        network.unfollow(1, 2).unwrap();
+        assert!(!network.follow(1, 2).unwrap());
+        assert_eq!(network.recent_follows[&1].len(), 2);
+
+        // Quotas are per user, and free up as the window slides
+        assert!(network.follow(2, 4).unwrap());
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(network.follow(1, 4).unwrap());
+
+        network.set_follow_quota(None);
+        for followee_id in 10..20 {
+            network.follow(1, followee_id).unwrap();
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! TLS settings for the gRPC server and client, with optional client certificate
+//! verification (mutual TLS).
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+/// Certificate and key the server presents, and the CA its clients must be signed by for mTLS
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerTls {
+    /// PEM certificate chain of the server
+    pub cert_path: PathBuf,
+
+    /// PEM private key of the server
+    pub key_path: PathBuf,
+
+    /// PEM CA certificate; when set, clients must present a certificate it signed
+    #[serde(default)]
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl ServerTls {
+    /// Read the certificates and key into a tonic server TLS config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be read.
+    pub fn load(&self) -> Result<ServerTlsConfig, String> {
+        let identity = Identity::from_pem(read_pem(&self.cert_path)?, read_pem(&self.key_path)?);
+        let mut config = ServerTlsConfig::new().identity(identity);
+        if let Some(client_ca_path) = &self.client_ca_path {
+            config = config.client_ca_root(Certificate::from_pem(read_pem(client_ca_path)?));
+        }
+        Ok(config)
+    }
+}
+
+/// CA the client trusts, and the certificate and key it presents for mTLS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientTls {
+    /// PEM CA certificate the server's certificate must be signed by
+    pub ca_cert_path: PathBuf,
+
+    /// PEM certificate and private key of the client, for servers requiring mTLS
+    pub identity: Option<(PathBuf, PathBuf)>,
+
+    /// Name to verify the server's certificate against, instead of the host of the URL
+    pub domain_name: Option<String>,
+}
+
+impl ClientTls {
+    /// Read the certificates and key into a tonic client TLS config
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be read.
+    pub fn load(&self) -> Result<ClientTlsConfig, String> {
+        let mut config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(read_pem(&self.ca_cert_path)?));
+        if let Some((cert_path, key_path)) = &self.identity {
+            config = config.identity(Identity::from_pem(read_pem(cert_path)?, read_pem(key_path)?));
+        }
+        if let Some(domain_name) = &self.domain_name {
+            config = config.domain_name(domain_name);
+        }
+        Ok(config)
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rcgen::{BasicConstraints, Certificate as GeneratedCertificate, CertificateParams, IsCa};
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::{Channel, Server};
+
+    use super::*;
+    use crate::server::social_network::social_network_service_client::SocialNetworkServiceClient;
+    use crate::server::social_network::social_network_service_server::SocialNetworkServiceServer;
+    use crate::server::social_network::CommitRequest;
+    use crate::server::SocialNetworkServer;
+
+    struct TestCertificates {
+        dir: PathBuf,
+        ca: PathBuf,
+        server: (PathBuf, PathBuf),
+        client: (PathBuf, PathBuf),
+    }
+
+    /// Generate a CA, and a server and a client certificate it signed, into a temporary directory
+    fn generate_certificates(name: &str) -> TestCertificates {
+        let dir = std::env::temp_dir().join(format!("socialnetwork-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut ca_params = CertificateParams::new(Vec::new());
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = GeneratedCertificate::from_params(ca_params).unwrap();
+        let ca_path = dir.join("ca.pem");
+        std::fs::write(&ca_path, ca.serialize_pem().unwrap()).unwrap();
+
+        let signed = |name: &str| {
+            let cert = GeneratedCertificate::from_params(CertificateParams::new(vec!["localhost".to_string()])).unwrap();
+            let cert_path = dir.join(format!("{name}.pem"));
+            let key_path = dir.join(format!("{name}.key"));
+            std::fs::write(&cert_path, cert.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+            std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+            (cert_path, key_path)
+        };
+        let server = signed("server");
+        let client = signed("client");
+
+        TestCertificates { ca: ca_path, server, client, dir }
+    }
+
+    async fn commit(channel: Channel) -> Result<u64, tonic::Status> {
+        let request = CommitRequest { expected_version: None, idempotency_key: None };
+        let response = SocialNetworkServiceClient::new(channel).commit(request).await?;
+        Ok(response.into_inner().version)
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        let certs = generate_certificates("tls");
+        let server_tls = ServerTls {
+            cert_path: certs.server.0.clone(),
+            key_path: certs.server.1.clone(),
+            client_ca_path: Some(certs.ca.clone()),
+        };
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}", listener.local_addr().unwrap());
+        let service = SocialNetworkServiceServer::from_arc(Arc::new(SocialNetworkServer::new()));
+        tokio::spawn(
+            Server::builder()
+                .tls_config(server_tls.load().unwrap())
+                .unwrap()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let connect = |identity: Option<(PathBuf, PathBuf)>| {
+            let client_tls = ClientTls { ca_cert_path: certs.ca.clone(), identity, domain_name: Some("localhost".into()) };
+            let endpoint = Channel::from_shared(url.clone()).unwrap().tls_config(client_tls.load().unwrap()).unwrap();
+            async move { endpoint.connect().await }
+        };
+
+        // A client certificate signed by the CA is accepted
+        assert_eq!(commit(connect(Some(certs.client.clone())).await.unwrap()).await.unwrap(), 1);
+
+        // Without one, the handshake is rejected
+        let rejected = match connect(None).await {
+            Ok(channel) => commit(channel).await.is_err(),
+            Err(_) => true,
+        };
+        assert!(rejected);
+
+        // Plaintext clients cannot talk to a TLS server
+        let plaintext = Channel::from_shared(url.replace("https", "http")).unwrap();
+        let rejected = match plaintext.connect().await {
+            Ok(channel) => commit(channel).await.is_err(),
+            Err(_) => true,
+        };
+        assert!(rejected);
+
+        assert!(ServerTls { cert_path: certs.dir.join("missing.pem"), ..server_tls }.load().is_err());
+        std::fs::remove_dir_all(&certs.dir).unwrap();
+    }
+}